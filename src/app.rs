@@ -1,148 +1,1079 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Sender, TryRecvError};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use eframe::CreationContext;
-use egui::Sense;
+use egui::text::LayoutJob;
+use egui::{FontId, Sense, TextFormat};
 use egui_file::FileDialog;
-use epaint::Vec2;
+use epaint::{Color32, Vec2};
 
 use crate::editor::Editor;
-use crate::project::{Component, Project};
+use crate::project::error::Location;
+use crate::project::format::Format;
+use crate::project::groove::Template;
+use crate::project::metadata::{Key, Mode, TimeSignature};
+use crate::project::{BeatsPerMinute, Component, Error as ProjectError, Groove, Metadata, Project};
+use crate::sound::effects::{Delay, Effects, Filter};
+use crate::sound::mixer::{Mix, Mixer, Output, Panning};
+use crate::sound::schedule::Schedule;
+use crate::sound::{self, Type as SoundType};
 
-enum State {
-	Home {
-		error: Option<String>,
-	},
-	Edit {
-		file_path: PathBuf,
-		project: Arc<Mutex<Project>>,
-		editor: Editor,
-		_project_stepper_handle: Sender<()>,
-	},
+/// An open project.
+struct Session {
+	file_path: PathBuf,
+	project: Arc<Mutex<Project>>,
+	editor: Editor,
+	/// The last error from saving or reloading the project.
+	file_error: Option<String>,
+	/// The error from the last autosave, cleared once autosaving works again.
+	autosave_error: Option<String>,
+	/// When the file was last modified, as of when it was last loaded or saved.
+	disk_modified: Option<SystemTime>,
+	/// Whether the file changed on disk while there were unsaved edits, so the user has to pick which to keep.
+	conflict: bool,
+	/// Whether the project settings window is shown.
+	settings_open: bool,
+	/// When the tap tempo button was recently pressed.
+	taps: Vec<Instant>,
+	/// Whether playback is shared with the other linked tabs, in time with them, instead of stopping when another tab is focused.
+	linked: Arc<AtomicBool>,
+}
+
+/// A project being played by the stepper thread.
+struct Player {
+	/// Gone once its tab is closed.
+	project: Weak<Mutex<Project>>,
+	/// Shared with [`Session::linked`].
+	linked: Arc<AtomicBool>,
+	schedule: Schedule,
+	mixer: Option<Mixer>,
+}
+
+/// A playback action from the transport bar, which may apply to several tabs.
+#[derive(Debug, Clone, Copy)]
+enum Transport {
+	Play,
+	Pause,
+	Stop,
 }
 
 pub struct App {
-	state: State,
+	/// The error from the last attempt to open a project, shown on the home tab.
+	open_error: Option<ProjectError>,
+	sessions: Vec<Session>,
+	/// The index in `sessions` of the shown tab, or `None` for the home tab.
+	focused: Option<usize>,
 	opening: FileDialog,
+	/// Projects that have been opened without closing cleanly, so their recovery file may be left over.
+	recovered: Vec<PathBuf>,
+	/// Recently opened projects, most recent first.
+	recent: Vec<PathBuf>,
+	/// Whether to open the most recent project at launch.
+	reopen_last: bool,
+	/// The project to open on the first frame, from the command line or [`App::reopen_last`].
+	launch_path: Option<PathBuf>,
+	/// Plays the project of every tab.
+	stepper: Sender<Player>,
+	/// The project of a tab with unsaved changes that is waiting for the user to confirm closing it.
+	closing: Option<PathBuf>,
+	/// A project being opened that has a recovery file, waiting for the user to pick whether to restore it.
+	recovering: Option<PathBuf>,
 }
 
+/// The storage key of [`App::recovered`].
+const RECOVERED_KEY: &str = "recovered";
+/// The storage key of [`App::recent`].
+const RECENT_KEY: &str = "recent";
+/// The storage key of [`App::reopen_last`].
+const REOPEN_LAST_KEY: &str = "reopen_last";
+const MAX_RECENT: usize = 10;
+
 impl App {
 	#[must_use]
-	pub fn new(_context: &CreationContext<'_>) -> Self {
+	pub fn new(context: &CreationContext<'_>) -> Self {
+		// in the background, so that neither startup nor the first notes stutter
+		std::thread::spawn(sound::bank::preload);
+
+		let mut recovered: Vec<PathBuf> = context
+			.storage
+			.and_then(|storage| eframe::get_value(storage, RECOVERED_KEY))
+			.unwrap_or_default();
+		recovered.retain(|file_path| recovery_path(file_path).exists());
+		let recent: Vec<PathBuf> = context
+			.storage
+			.and_then(|storage| eframe::get_value(storage, RECENT_KEY))
+			.unwrap_or_default();
+		let reopen_last = context
+			.storage
+			.and_then(|storage| eframe::get_value(storage, REOPEN_LAST_KEY))
+			.unwrap_or(false);
+
+		// `hanlon path/to/project.han`
+		let launch_path = std::env::args_os()
+			.nth(1)
+			.map(PathBuf::from)
+			.or_else(|| recent.first().filter(|_| reopen_last).cloned());
+
 		Self {
-			state: State::Home { error: None },
-			opening: FileDialog::open_file(None).filter("han".to_owned()),
+			open_error: None,
+			sessions: Vec::new(),
+			focused: None,
+			opening: open_dialog(Format::Json),
+			recovered,
+			recent,
+			reopen_last,
+			launch_path,
+			stepper: spawn_stepper(),
+			closing: None,
+			recovering: None,
 		}
 	}
-}
 
-impl eframe::App for App {
-	fn update(&mut self, context: &egui::Context, _frame: &mut eframe::Frame) {
-		match &mut self.state {
-			State::Home { error } => {
-				egui::CentralPanel::default().show(context, |ui| {
-					ui.vertical_centered(|ui| {
-						ui.heading("Hanlon");
+	/// Opens the project read from `read_path`, which is saved back to `file_path`.
+	fn open(&mut self, file_path: PathBuf, read_path: &Path, frame: &mut eframe::Frame) {
+		// the path identifies the project, so it mustn't depend on the working directory or how the file was reached
+		let restoring = read_path != file_path;
+		let file_path = std::fs::canonicalize(&file_path).unwrap_or(file_path);
+		let read_path = if restoring { read_path } else { &file_path };
 
-						if ui.button("New").clicked() {
-							todo!("new")
-						}
+		if let Some(index) = self
+			.sessions
+			.iter()
+			.position(|session| session.file_path == file_path)
+		{
+			self.focus(Some(index));
+			return;
+		}
+		// opening the project itself would autosave over the unsaved changes left over from last time
+		if !restoring && recovery_path(&file_path).exists() {
+			self.recovering = Some(file_path);
+			return;
+		}
+
+		match Project::read(read_path) {
+			Ok(project) => {
+				if !self.recovered.contains(&file_path) {
+					self.recovered.push(file_path.clone());
+				}
+				self.recent.retain(|other| *other != file_path);
+				self.recent.insert(0, file_path.clone());
+				self.recent.truncate(MAX_RECENT);
+				self.remember(frame);
+
+				self.open_error = None;
+				let mut session = Session::new(file_path, project, &self.stepper);
+				// restored changes haven't been saved to the project yet
+				session.editor.modified = restoring;
+				self.sessions.push(session);
+				self.focus(Some(self.sessions.len() - 1));
+			}
+			Err(error) => {
+				self.open_error = Some(error);
+				self.focus(None);
+			}
+		}
+	}
+
+	/// Stores which projects are open and recent right away, since a crash won't give us the chance to later.
+	fn remember(&self, frame: &mut eframe::Frame) {
+		if let Some(storage) = frame.storage_mut() {
+			eframe::set_value(storage, RECOVERED_KEY, &self.recovered);
+			eframe::set_value(storage, RECENT_KEY, &self.recent);
+			storage.flush();
+		}
+	}
+
+	/// Shows another tab, pausing the previous one unless it's linked.
+	fn focus(&mut self, focused: Option<usize>) {
+		if let Some(previous) = self.focused.filter(|&index| Some(index) != focused) {
+			let session = &self.sessions[previous];
+			if !session.linked.load(Ordering::Relaxed) {
+				session.apply(Transport::Pause);
+			}
+		}
+		self.focused = focused;
+	}
+
+	/// Closes a tab. Closing is the same as exiting, so there is nothing left to recover.
+	fn close(&mut self, index: usize, frame: &mut eframe::Frame) {
+		let session = self.sessions.remove(index);
+		let _ = std::fs::remove_file(recovery_path(&session.file_path));
+		self.recovered.retain(|other| *other != session.file_path);
+		self.remember(frame);
+		self.focused = match self.focused {
+			Some(focused) if focused == index => None,
+			Some(focused) if focused > index => Some(focused - 1),
+			focused => focused,
+		};
+	}
+
+	fn show_tabs(&mut self, context: &egui::Context, frame: &mut eframe::Frame) {
+		let mut focusing = None;
+		let mut closing = None;
+		egui::TopBottomPanel::top("tabs").show(context, |ui| {
+			ui.horizontal(|ui| {
+				if ui
+					.selectable_label(self.focused.is_none(), "Home")
+					.clicked()
+				{
+					focusing = Some(None);
+				}
+				for (index, session) in self.sessions.iter().enumerate() {
+					ui.separator();
+					let name = session
+						.file_path
+						.file_name()
+						.unwrap_or_default()
+						.to_string_lossy();
+					let unsaved = if session.editor.modified { "*" } else { "" };
+					if ui
+						.selectable_label(self.focused == Some(index), format!("{name}{unsaved}"))
+						.on_hover_text(session.file_path.display().to_string())
+						.clicked()
+					{
+						focusing = Some(Some(index));
+					}
+					if ui.small_button("×").on_hover_text("Close").clicked() {
+						closing = Some(index);
+					}
+				}
+			});
+		});
+
+		if let Some(index) = closing {
+			if self.sessions[index].editor.modified {
+				self.closing = Some(self.sessions[index].file_path.clone());
+				self.focus(Some(index));
+			} else {
+				self.close(index, frame);
+			}
+		} else if let Some(focused) = focusing {
+			self.focus(focused);
+		}
+	}
+
+	/// Asks whether to save a tab with unsaved changes before closing it.
+	fn show_close_prompt(&mut self, context: &egui::Context, frame: &mut eframe::Frame) {
+		let Some(index) = self.closing.as_ref().and_then(|file_path| {
+			self
+				.sessions
+				.iter()
+				.position(|session| session.file_path == *file_path)
+		}) else {
+			self.closing = None;
+			return;
+		};
+
+		let mut closing = false;
+		egui::Window::new("Unsaved changes")
+			.collapsible(false)
+			.resizable(false)
+			.show(context, |ui| {
+				let session = &mut self.sessions[index];
+				ui.label(format!(
+					"{} has unsaved changes.",
+					session
+						.file_path
+						.file_name()
+						.unwrap_or_default()
+						.to_string_lossy()
+				));
+				ui.horizontal(|ui| {
+					if ui.button("Save").clicked() {
+						session.save();
+						// if saving failed, the error is shown in the tab and it stays open
+						closing = !session.editor.modified;
+						self.closing = None;
+					}
+					if ui.button("Discard").clicked() {
+						closing = true;
+					}
+					if ui.button("Cancel").clicked() {
+						self.closing = None;
+					}
+				});
+			});
+
+		if closing {
+			self.closing = None;
+			self.close(index, frame);
+		}
+	}
+
+	/// Asks whether to restore the leftover changes of a project before opening it.
+	fn show_recover_prompt(&mut self, context: &egui::Context, frame: &mut eframe::Frame) {
+		let Some(file_path) = self.recovering.clone() else {
+			return;
+		};
+
+		let mut restoring = None;
+		egui::Window::new("Restore unsaved changes?")
+			.collapsible(false)
+			.resizable(false)
+			.show(context, |ui| {
+				ui.label(format!(
+					"{} has unsaved changes left over from when it was last open.",
+					file_path.file_name().unwrap_or_default().to_string_lossy()
+				));
+				ui.horizontal(|ui| {
+					if ui.button("Restore").clicked() {
+						restoring = Some(true);
+					}
+					if ui.button("Discard").clicked() {
+						restoring = Some(false);
+					}
+					if ui.button("Cancel").clicked() {
+						self.recovering = None;
+					}
+				});
+			});
+
+		if let Some(restoring) = restoring {
+			self.recovering = None;
+			if restoring {
+				self.open(file_path.clone(), &recovery_path(&file_path), frame);
+			} else {
+				self.discard_recovery(&file_path);
+				self.open(file_path.clone(), &file_path, frame);
+			}
+		}
+	}
+
+	/// Deletes the leftover changes of the project at `file_path`.
+	fn discard_recovery(&mut self, file_path: &Path) {
+		// it's fine if the recovery file is already gone
+		let _ = std::fs::remove_file(recovery_path(file_path));
+		self.recovered.retain(|other| other != file_path);
+	}
+
+	fn show_home(&mut self, context: &egui::Context, frame: &mut eframe::Frame) {
+		// the recovery files of open projects are autosaves, not leftovers
+		let leftovers: Vec<PathBuf> = self
+			.recovered
+			.iter()
+			.filter(|file_path| {
+				!self
+					.sessions
+					.iter()
+					.any(|session| session.file_path == **file_path)
+			})
+			.cloned()
+			.collect();
+		let mut restoring = None;
+		let mut discarding = None;
+		let mut reopening = None;
+
+		egui::CentralPanel::default().show(context, |ui| {
+			ui.vertical_centered(|ui| {
+				ui.heading("Hanlon");
+
+				if ui.button("New").clicked() {
+					todo!("new")
+				}
+
+				// the dialog can only filter by one extension, so each format has its own button
+				for format in Format::ALL {
+					let label = match format {
+						Format::Json => "Open",
+						Format::Text => "Open text project",
+						Format::Binary => "Open binary project",
+					};
+					if ui.button(label).clicked() {
+						self.opening = open_dialog(format);
+						self.opening.open();
+					}
+				}
 
-						if ui.button("Open").clicked() {
-							self.opening.open();
+				if let Some(error) = &self.open_error {
+					let error_color = context.style().visuals.error_fg_color;
+					ui.colored_label(error_color, format!("error while reading project: {error}"));
+					if let Some(location) = error.location() {
+						ui.label(format!("at {location}:"));
+						ui.label(highlighted_source(location, error_color));
+					}
+				}
+
+				if !self.recent.is_empty() {
+					ui.separator();
+					ui.label("Recent projects:");
+					for file_path in &self.recent {
+						// the project may have been moved or deleted since
+						let Ok(modified) =
+							std::fs::metadata(file_path).and_then(|metadata| metadata.modified())
+						else {
+							continue;
+						};
+						let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+						if ui
+							.button(format!("{name} (modified {})", describe_age(modified)))
+							.on_hover_text(file_path.display().to_string())
+							.clicked()
+						{
+							reopening = Some(file_path.clone());
 						}
+					}
+					ui.checkbox(&mut self.reopen_last, "Reopen the last project at launch");
+				}
+
+				if !leftovers.is_empty() {
+					ui.separator();
+					ui.label(
+						"These projects weren't saved or closed properly. Their unsaved changes can be restored.",
+					);
+					for file_path in &leftovers {
+						ui.horizontal(|ui| {
+							ui.label(file_path.display().to_string());
+							if ui.button("Restore").clicked() {
+								restoring = Some(file_path.clone());
+							}
+							if ui.button("Discard").clicked() {
+								discarding = Some(file_path.clone());
+							}
+						});
+					}
+				}
+			});
+		});
 
-						if let Some(error) = error {
-							ui.colored_label(context.style().visuals.error_fg_color, error);
+		if let Some(file_path) = discarding {
+			self.discard_recovery(&file_path);
+		} else if let Some(file_path) = restoring {
+			let read_path = recovery_path(&file_path);
+			self.open(file_path, &read_path, frame);
+		} else if let Some(file_path) = reopening {
+			self.open(file_path.clone(), &file_path, frame);
+		}
+	}
+}
+
+/// How long ago `time` was, roughly.
+fn describe_age(time: SystemTime) -> String {
+	let seconds = SystemTime::now()
+		.duration_since(time)
+		.unwrap_or_default()
+		.as_secs();
+	let (amount, unit) = match seconds {
+		0..=59 => return "just now".to_owned(),
+		60..=3599 => (seconds / 60, "minute"),
+		3600..=86_399 => (seconds / 3600, "hour"),
+		_ => (seconds / 86_400, "day"),
+	};
+	let plural = if amount == 1 { "" } else { "s" };
+	format!("{amount} {unit}{plural} ago")
+}
+
+/// Where the project at `file_path` is autosaved to, e.g. `song.recovery.han` for `song.han`.
+fn recovery_path(file_path: &Path) -> PathBuf {
+	let mut name = file_path.file_stem().unwrap_or_default().to_owned();
+	name.push(".recovery");
+	if let Some(extension) = file_path.extension() {
+		name.push(".");
+		name.push(extension);
+	}
+	file_path.with_file_name(name)
+}
+
+fn open_dialog(format: Format) -> FileDialog {
+	FileDialog::open_file(None).filter(format.extension().to_owned())
+}
+
+/// The line of source that `location` refers to, with the offending character highlighted.
+fn highlighted_source(location: &Location, highlight: Color32) -> LayoutJob {
+	// project files can be a single very long line, so only show the surroundings.
+	const CONTEXT_CHARS: usize = 40;
+
+	let line = &location.source_line;
+	let byte_index = |char_index: usize| {
+		line
+			.char_indices()
+			.nth(char_index)
+			.map_or(line.len(), |(index, _)| index)
+	};
+	let at = location.column.saturating_sub(1);
+	let start = byte_index(at.saturating_sub(CONTEXT_CHARS));
+	let at_start = byte_index(at);
+	let at_end = byte_index(at + 1);
+	let end = byte_index(at + 1 + CONTEXT_CHARS);
+
+	let font_id = FontId::monospace(14.0);
+	let normal = TextFormat {
+		font_id: font_id.clone(),
+		..Default::default()
+	};
+	let mut job = LayoutJob::default();
+	if start > 0 {
+		job.append("…", 0.0, normal.clone());
+	}
+	job.append(&line[start..at_start], 0.0, normal.clone());
+	job.append(
+		&line[at_start..at_end],
+		0.0,
+		TextFormat {
+			font_id,
+			color: Color32::WHITE,
+			background: highlight,
+			..Default::default()
+		},
+	);
+	job.append(&line[at_end..end], 0.0, normal.clone());
+	if end < line.len() {
+		job.append("…", 0.0, normal);
+	}
+	job
+}
+
+/// Plays the projects sent to it through one audio output until the sender is dropped, so that linked projects can share a clock.
+fn spawn_stepper() -> Sender<Player> {
+	let (send, recv) = channel::<Player>();
+
+	std::thread::spawn(move || {
+		let audio = rodio::OutputStream::try_default().ok();
+		let mut output = audio.as_ref().map(|(_stream, audio)| {
+			let (output, source) = Output::new();
+			audio.play_raw(source).unwrap();
+			output
+		});
+		// with audio, the schedule decides how far to step, so wake up often enough to stay ahead of it
+		let sleep_time = if output.is_some() {
+			Duration::from_millis(5)
+		} else {
+			Duration::from_secs_f32(1.0 / 60.0) // 60 fps
+		};
+
+		let mut players = Vec::new();
+		loop {
+			match recv.try_recv() {
+				Ok(mut player) => {
+					player.mixer = output.as_mut().map(Output::mixer);
+					players.push(player);
+					continue;
+				}
+				Err(TryRecvError::Empty) => {}
+				Err(TryRecvError::Disconnected) => return,
+			}
+			// dropping the mixer of a closed tab stops what it was playing
+			players.retain(|player| player.project.strong_count() > 0);
+
+			for index in 0..players.len() {
+				let in_time_with = players[index]
+					.linked
+					.load(Ordering::Relaxed)
+					.then(|| {
+						players
+							.iter()
+							.enumerate()
+							.filter(|&(other, player)| other != index && player.linked.load(Ordering::Relaxed))
+							.find_map(|(_, player)| player.schedule.origin_frame())
+					})
+					.flatten();
+				let player = &mut players[index];
+				let Some(project) = player.project.upgrade() else {
+					continue;
+				};
+				let mut project = project.lock().unwrap();
+				if let Some(mixer) = &mut player.mixer {
+					mixer.configure(&project.mix, project.current_tempo());
+					player.schedule.run(&mut project, mixer, in_time_with);
+				} else if !project.paused {
+					let _ = project.step_pellets();
+				}
+			}
+			std::thread::sleep(sleep_time);
+		}
+	});
+
+	send
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+	std::fs::metadata(path)
+		.and_then(|metadata| metadata.modified())
+		.ok()
+}
+
+fn edit_metadata(ui: &mut egui::Ui, metadata: &mut Metadata) {
+	egui::Grid::new("metadata").num_columns(2).show(ui, |ui| {
+		ui.label("Title");
+		ui.text_edit_singleline(&mut metadata.title);
+		ui.end_row();
+
+		ui.label("Author");
+		ui.text_edit_singleline(&mut metadata.author);
+		ui.end_row();
+
+		ui.label("Time signature");
+		ui.horizontal(|ui| {
+			let TimeSignature { beats, note_value } = &mut metadata.time_signature;
+			ui.add(egui::DragValue::new(beats).clamp_range(1..=u8::MAX));
+			ui.label("/");
+			egui::ComboBox::from_id_source("note_value")
+				.selected_text(note_value.to_string())
+				.show_ui(ui, |ui| {
+					for value in TimeSignature::NOTE_VALUES {
+						ui.selectable_value(note_value, value, value.to_string());
+					}
+				});
+		});
+		ui.end_row();
+
+		ui.label("Key");
+		ui.horizontal(|ui| {
+			let key = &mut metadata.key;
+			egui::ComboBox::from_id_source("key_root")
+				.selected_text(key.root_name())
+				.show_ui(ui, |ui| {
+					for (root, name) in (0..).zip(Key::ROOT_NAMES) {
+						ui.selectable_value(&mut key.root, root, name);
+					}
+				});
+			ui.selectable_value(&mut key.mode, Mode::Major, "Major");
+			ui.selectable_value(&mut key.mode, Mode::Minor, "Minor");
+		});
+		ui.end_row();
+
+		ui.label("Notes");
+		ui.text_edit_multiline(&mut metadata.notes);
+		ui.end_row();
+
+		let timestamp = |seconds: Option<u64>| {
+			seconds.map_or_else(
+				|| "never saved".to_owned(),
+				|seconds| describe_age(UNIX_EPOCH + Duration::from_secs(seconds)),
+			)
+		};
+		ui.label("Created");
+		ui.weak(timestamp(metadata.created));
+		ui.end_row();
+
+		ui.label("Modified");
+		ui.weak(timestamp(metadata.modified));
+		ui.end_row();
+	});
+}
+
+/// Records a press of the tap tempo button, returning the tempo it was tapped at once there are enough taps.
+fn tap_tempo(taps: &mut Vec<Instant>) -> Option<BeatsPerMinute> {
+	// a longer pause starts a new tempo
+	const FORGET_AFTER: Duration = Duration::from_secs(2);
+	const MAX_TAPS: usize = 8;
+
+	let now = Instant::now();
+	if taps.last().is_some_and(|&last| now - last > FORGET_AFTER) {
+		taps.clear();
+	}
+	taps.push(now);
+	if taps.len() > MAX_TAPS {
+		taps.remove(0);
+	}
+
+	let (&first, &last) = (taps.first()?, taps.last()?);
+	let intervals = az::cast::<_, u32>(taps.len() - 1);
+	(intervals > 0).then(|| BeatsPerMinute(60.0 / ((last - first) / intervals).as_secs_f32()))
+}
+
+fn edit_groove(ui: &mut egui::Ui, groove: &mut Groove) {
+	ui.horizontal(|ui| {
+		ui.label("Groove");
+		egui::ComboBox::from_id_source("groove_template")
+			.selected_text(groove.template.name())
+			.show_ui(ui, |ui| {
+				for template in Template::ALL {
+					ui.selectable_value(&mut groove.template, template, template.name());
+				}
+			});
+		ui.add(egui::Slider::new(&mut groove.amount, 0.0..=1.0).text("Amount"));
+	});
+}
+
+fn edit_mix(ui: &mut egui::Ui, mix: &mut Mix) {
+	ui.add(
+		egui::DragValue::new(&mut mix.max_voices)
+			.prefix("Notes at once per instrument: ")
+			.clamp_range(1..=u8::MAX),
+	);
+	for (ty, name) in SoundType::ALL
+		.into_iter()
+		.filter_map(|ty| Some((ty, ty.name()?)))
+	{
+		let mut gain = mix.gain(ty);
+		if ui
+			.add(egui::Slider::new(&mut gain, 0.0..=Mix::MAX_GAIN).text(format!("{name} volume")))
+			.changed()
+		{
+			mix.gains.insert(name.to_owned(), gain);
+		}
+		let mut pan = mix.pan(ty);
+		if ui
+			.add(egui::Slider::new(&mut pan, -1.0..=1.0).text(format!("{name} pan")))
+			.changed()
+		{
+			mix.pans.insert(name.to_owned(), pan);
+		}
+	}
+	ui.checkbox(&mut mix.limiter, "Limit loud passages");
+
+	ui.horizontal(|ui| {
+		ui.label("Pan");
+		egui::ComboBox::from_id_source("panning")
+			.selected_text(mix.panning.name())
+			.show_ui(ui, |ui| {
+				for panning in [
+					Panning::Instrument,
+					Panning::Viewport,
+					Panning::Span { left: -8, right: 8 },
+				] {
+					let selected = std::mem::discriminant(&mix.panning) == std::mem::discriminant(&panning);
+					if ui.selectable_label(selected, panning.name()).clicked() && !selected {
+						mix.panning = panning;
+					}
+				}
+			});
+		if let Panning::Span { left, right } = &mut mix.panning {
+			let (lowest_right, highest_left) = (left.saturating_add(1), right.saturating_sub(1));
+			ui.add(
+				egui::DragValue::new(left)
+					.clamp_range(i16::MIN..=highest_left)
+					.prefix("from column "),
+			);
+			ui.add(
+				egui::DragValue::new(right)
+					.clamp_range(lowest_right..=i16::MAX)
+					.prefix("to "),
+			);
+		}
+	});
+}
+
+fn edit_effects(ui: &mut egui::Ui, effects: &mut Effects) {
+	let Effects {
+		delay,
+		reverb,
+		low_pass,
+		high_pass,
+	} = effects;
+	ui.checkbox(&mut delay.enabled, "Delay");
+	ui.add_enabled_ui(delay.enabled, |ui| {
+		ui.add(
+			egui::Slider::new(&mut delay.beats, Delay::BEATS[0]..=Delay::BEATS[1])
+				.logarithmic(true)
+				.text("Beats"),
+		);
+		ui.add(egui::Slider::new(&mut delay.feedback, 0.0..=Delay::MAX_FEEDBACK).text("Feedback"));
+		ui.add(egui::Slider::new(&mut delay.mix, 0.0..=1.0).text("Mix"));
+	});
+	ui.checkbox(&mut reverb.enabled, "Reverb");
+	ui.add_enabled_ui(reverb.enabled, |ui| {
+		ui.add(egui::Slider::new(&mut reverb.room_size, 0.0..=1.0).text("Room size"));
+		ui.add(egui::Slider::new(&mut reverb.damping, 0.0..=1.0).text("Damping"));
+		ui.add(egui::Slider::new(&mut reverb.mix, 0.0..=1.0).text("Mix"));
+	});
+	for (filter, name) in [
+		(low_pass, "Low-pass filter"),
+		(high_pass, "High-pass filter"),
+	] {
+		ui.horizontal(|ui| {
+			ui.checkbox(&mut filter.enabled, name);
+			ui.add_enabled(
+				filter.enabled,
+				egui::Slider::new(&mut filter.cutoff, Filter::CUTOFFS[0]..=Filter::CUTOFFS[1])
+					.logarithmic(true)
+					.suffix(" Hz"),
+			);
+		});
+	}
+}
+
+impl Session {
+	fn new(file_path: PathBuf, project: Project, stepper: &Sender<Player>) -> Self {
+		let project = Arc::new(Mutex::new(project));
+		let linked = Arc::new(AtomicBool::new(false));
+		// the stepper only stops when the app does
+		let _ = stepper.send(Player {
+			project: Arc::downgrade(&project),
+			linked: Arc::clone(&linked),
+			schedule: Schedule::default(),
+			mixer: None,
+		});
+		Self {
+			disk_modified: modified_time(&file_path),
+			file_path,
+			project,
+			editor: Editor::default(),
+			file_error: None,
+			autosave_error: None,
+			conflict: false,
+			settings_open: false,
+			taps: Vec::new(),
+			linked,
+		}
+	}
+
+	fn save(&mut self) {
+		let mut project = self.project.lock().unwrap();
+		project.metadata.touch();
+		match project.write(&self.file_path) {
+			Ok(()) => {
+				self.disk_modified = modified_time(&self.file_path);
+				self.editor.modified = false;
+				self.conflict = false;
+				self.file_error = None;
+			}
+			Err(error) => self.file_error = Some(format!("error while saving project: {error}")),
+		}
+	}
+
+	/// Replaces the project with what is on disk, keeping the view and whether it's playing.
+	fn reload(&mut self) {
+		match Project::read(&self.file_path) {
+			Ok(mut reloaded) => {
+				let mut project = self.project.lock().unwrap();
+				reloaded.paused = project.paused;
+				*project = reloaded;
+				self.editor.modified = false;
+				self.conflict = false;
+				self.file_error = None;
+			}
+			// the file may be halfway through being written, in which case it'll change again shortly.
+			Err(error) => self.file_error = Some(format!("error while reloading project: {error}")),
+		}
+	}
+
+	/// Reloads the project if another program has changed it.
+	fn check_disk(&mut self) {
+		let modified = modified_time(&self.file_path);
+		if modified.is_none() || modified == self.disk_modified {
+			return;
+		}
+		self.disk_modified = modified;
+		if self.editor.modified {
+			self.conflict = true;
+		} else {
+			self.reload();
+		}
+	}
+
+	fn apply(&self, transport: Transport) {
+		let mut project = self.project.lock().unwrap();
+		match transport {
+			Transport::Play => project.paused = false,
+			Transport::Pause => project.paused = true,
+			Transport::Stop => project.reset(),
+		}
+	}
+
+	#[must_use]
+	fn show_transport(&mut self, context: &egui::Context) -> Option<Transport> {
+		let mut transport = None;
+		egui::TopBottomPanel::top("transport").show(context, |ui| {
+			ui.horizontal(|ui| {
+				if ui.button("Save").clicked() {
+					self.save();
+				}
+				if ui.button("Settings").clicked() {
+					self.settings_open = !self.settings_open;
+				}
+				for error in self.file_error.iter().chain(&self.autosave_error) {
+					ui.colored_label(context.style().visuals.error_fg_color, error);
+				}
+				let mut project = self.project.lock().unwrap();
+				ui.separator();
+				if project.paused {
+					if ui.button("Play").clicked() {
+						transport = Some(Transport::Play);
+					}
+				} else if ui.button("Pause").clicked() {
+					transport = Some(Transport::Pause);
+				}
+				if ui.button("Stop").clicked() {
+					transport = Some(Transport::Stop);
+				}
+				let mut linked = self.linked.load(Ordering::Relaxed);
+				if ui
+					.checkbox(&mut linked, "Link")
+					.on_hover_text("Play in sync with the other linked tabs")
+					.changed()
+				{
+					self.linked.store(linked, Ordering::Relaxed);
+				}
+				ui.separator();
+				let mut tempo = project.current_tempo();
+				let tempo_response = ui.add(
+					egui::DragValue::new(&mut tempo.0)
+						.suffix(" BPM")
+						.clamp_range(BeatsPerMinute::MIN.0..=BeatsPerMinute::MAX.0),
+				);
+				if tempo_response.changed() {
+					project.set_tempo(tempo);
+					self.editor.modified = true;
+				}
+				if ui
+					.button("Tap")
+					.on_hover_text("Tap along to set the tempo")
+					.clicked()
+				{
+					if let Some(tempo) = tap_tempo(&mut self.taps) {
+						project.set_tempo(tempo);
+						self.editor.modified = true;
+					}
+				}
+				ui.separator();
+				if ui
+					.button("Reroll")
+					.on_hover_text(format!("Seed: {}", project.seed))
+					.clicked()
+				{
+					project.reroll();
+					self.editor.modified = true;
+				}
+			});
+		});
+
+		transport
+	}
+
+	#[must_use]
+	fn show(&mut self, context: &egui::Context) -> Option<Transport> {
+		self.check_disk();
+		if self.conflict {
+			egui::Window::new("File changed")
+				.collapsible(false)
+				.resizable(false)
+				.show(context, |ui| {
+					ui.label(
+						"The project was changed by another program, but it also has unsaved changes here.",
+					);
+					ui.horizontal(|ui| {
+						if ui.button("Reload").clicked() {
+							self.reload();
+						}
+						if ui.button("Keep mine").clicked() {
+							self.conflict = false;
 						}
 					});
 				});
+		}
 
-				if self.opening.show(context).selected() {
-					*error = None;
-					let file_path = self.opening.path().unwrap();
-					match Project::read(&file_path) {
-						Ok(project) => {
-							let project = Arc::new(Mutex::new(project));
-							let (send, recv) = channel();
-
-							std::thread::spawn({
-								let project = Arc::clone(&project);
-								move || {
-									let audio = rodio::OutputStream::try_default().ok();
-									let sleep_time = Duration::from_secs_f32(1.0 / 60.0); // 60 fps
-
-									// stop when the sender is dropped
-									while let Err(TryRecvError::Empty) = recv.try_recv() {
-										let mut project = project.lock().unwrap();
-										for sound in project.step_pellets() {
-											if let Some((_stream, audio)) = &audio {
-												sound.play(audio);
-											}
-										}
-										drop(project);
-										std::thread::sleep(sleep_time);
-									}
-								}
-							});
-
-							self.state = State::Edit {
-								project,
-								file_path,
-								editor: Editor::default(),
-								_project_stepper_handle: send,
+		egui::Window::new("Project settings")
+			.open(&mut self.settings_open)
+			.show(context, |ui| {
+				let mut project = self.project.lock().unwrap();
+				let (metadata, groove, mix) = (
+					project.metadata.clone(),
+					project.groove,
+					project.mix.clone(),
+				);
+				edit_metadata(ui, &mut project.metadata);
+				ui.separator();
+				edit_groove(ui, &mut project.groove);
+				ui.separator();
+				edit_mix(ui, &mut project.mix);
+				ui.separator();
+				edit_effects(ui, &mut project.mix.effects);
+				self.editor.modified |=
+					project.metadata != metadata || project.groove != groove || project.mix != mix;
+			});
+
+		let transport = self.show_transport(context);
+
+		egui::SidePanel::left("palette")
+			.default_width(120.0)
+			.min_width(120.0)
+			.show(context, |ui| {
+				egui::ScrollArea::both().show(ui, |ui| {
+					ui.vertical_centered(|ui| {
+						ui.heading("Palette");
+					});
+
+					ui.horizontal_wrapped(|ui| {
+						for component in Component::PALETTE_LIST {
+							let (rect, response) =
+								ui.allocate_exact_size(Vec2::splat(Editor::RELATIVE_COMPONENT_SIZE), Sense::drag());
+							if response.drag_started() {
+								assert!(self.editor.dragging.replace(*component).is_none());
+							} else if response.drag_released() {
+								self.editor.drag_released_from_outer =
+									Some(response.interact_pointer_pos().unwrap());
 							}
+							component.draw(ui.painter(), rect);
+							response.on_hover_text(component.name());
+						}
+					});
+				});
+			});
+
+		egui::CentralPanel::default()
+			.frame(egui::Frame {
+				inner_margin: egui::style::Margin::same(0.0),
+				..Default::default()
+			})
+			.show(context, |ui| {
+				self.editor.show(ui, &mut self.project.lock().unwrap());
+				context.request_repaint();
+			});
+
+		transport
+	}
+}
+
+impl eframe::App for App {
+	fn update(&mut self, context: &egui::Context, frame: &mut eframe::Frame) {
+		if let Some(file_path) = self.launch_path.take() {
+			self.open(file_path.clone(), &file_path, frame);
+		}
+
+		self.show_tabs(context, frame);
+		self.show_close_prompt(context, frame);
+		self.show_recover_prompt(context, frame);
+		match self.focused {
+			None => self.show_home(context, frame),
+			Some(index) => {
+				if let Some(transport) = self.sessions[index].show(context) {
+					if self.sessions[index].linked.load(Ordering::Relaxed) {
+						let linked = self
+							.sessions
+							.iter()
+							.filter(|session| session.linked.load(Ordering::Relaxed));
+						for session in linked {
+							session.apply(transport);
 						}
-						Err(read_error) => *error = Some(format!("error while reading project: {read_error}")),
+					} else {
+						self.sessions[index].apply(transport);
 					}
 				}
 			}
-			State::Edit {
-				editor,
-				file_path,
-				project,
-				_project_stepper_handle: _,
-			} => {
-				egui::SidePanel::left("palette")
-					.default_width(120.0)
-					.min_width(120.0)
-					.show(context, |ui| {
-						egui::ScrollArea::both().show(ui, |ui| {
-							ui.vertical_centered(|ui| {
-								ui.heading("Palette");
-							});
-
-							ui.horizontal_wrapped(|ui| {
-								for component in Component::PALETTE_LIST {
-									let (rect, response) = ui.allocate_exact_size(
-										Vec2::splat(Editor::RELATIVE_COMPONENT_SIZE),
-										Sense::drag(),
-									);
-									if response.drag_started() {
-										assert!(editor.dragging.replace(*component).is_none());
-									} else if response.drag_released() {
-										editor.drag_released_from_outer =
-											Some(response.interact_pointer_pos().unwrap());
-									}
-									component.draw(ui.painter(), rect);
-									response.on_hover_text(component.name());
-								}
-							});
-						});
-					});
+		}
 
-				egui::CentralPanel::default()
-					.frame(egui::Frame {
-						inner_margin: egui::style::Margin::same(0.0),
-						..Default::default()
-					})
-					.show(context, |ui| {
-						editor.show(ui, &mut project.lock().unwrap());
-						context.request_repaint();
-					});
-			}
+		if self.opening.show(context).selected() {
+			let file_path = self.opening.path().unwrap();
+			self.open(file_path.clone(), &file_path, frame);
+		}
+	}
+
+	/// Called periodically, so this is also where the open project is autosaved.
+	fn save(&mut self, storage: &mut dyn eframe::Storage) {
+		for session in &mut self.sessions {
+			let project = session.project.lock().unwrap();
+			session.autosave_error = project
+				.write(&recovery_path(&session.file_path))
+				.err()
+				.map(|error| format!("error while autosaving project: {error}"));
+		}
+		eframe::set_value(storage, RECOVERED_KEY, &self.recovered);
+		eframe::set_value(storage, RECENT_KEY, &self.recent);
+		eframe::set_value(storage, REOPEN_LAST_KEY, &self.reopen_last);
+	}
+
+	fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+		// saved projects were closed properly, so there's nothing to recover. unsaved changes are kept to be restored next time.
+		for session in self
+			.sessions
+			.iter()
+			.filter(|session| !session.editor.modified)
+		{
+			let _ = std::fs::remove_file(recovery_path(&session.file_path));
 		}
 	}
 }
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use egui::{Color32, Painter, Pos2, Rect, Sense, Stroke, Ui, Vec2};
 
 use crate::project::{Component, Position as ComponentPos, Project};
@@ -7,6 +9,10 @@ pub struct Editor {
 	pub zoom: f32,
 	pub dragging: Option<Component>,
 	pub drag_released_from_outer: Option<Pos2>,
+	/// The component whose settings are shown in the context menu.
+	pub context_menu_target: Option<ComponentPos>,
+	/// Whether the project has been changed since it was last loaded or saved.
+	pub modified: bool,
 }
 
 impl Default for Editor {
@@ -16,6 +22,8 @@ impl Default for Editor {
 			zoom: 1.0,
 			dragging: None,
 			drag_released_from_outer: None,
+			context_menu_target: None,
+			modified: false,
 		}
 	}
 }
@@ -44,10 +52,12 @@ impl Editor {
 			if let Some(component_pos) = self.window_pos_to_component_pos(window_pos, rect) {
 				if let Some(component) = project.components.remove(&component_pos) {
 					self.dragging = Some(component);
+					self.modified = true;
 				}
 			}
 		} else if released || response.drag_released() || self.drag_released_from_outer.is_some() {
 			if let Some(dragging) = self.dragging.take() {
+				self.modified = true;
 				// `interact_pointer_pos` returns `Some` only if the pointer event was within our region.
 				if let Some(window_pos) = self
 					.drag_released_from_outer
@@ -69,10 +79,14 @@ impl Editor {
 			self.position += response.drag_delta() / self.zoom;
 		}
 
+		let column_at = |x: f32| (x - self.origin(rect).x) / self.component_size();
+		project.viewport = Some(column_at(rect.left())..=column_at(rect.right()));
+
 		let painter = ui.painter().with_clip_rect(rect);
 		painter.rect_filled(rect, 0.0, ui.style().visuals.window_fill());
 
 		self.draw_grid(&painter, rect);
+		self.draw_portal_links(&painter, project, rect);
 
 		for ComponentWithPosition {
 			window_pos,
@@ -101,6 +115,26 @@ impl Editor {
 				);
 			}
 		}
+
+		if response.secondary_clicked() {
+			self.context_menu_target = response
+				.interact_pointer_pos()
+				.and_then(|window_pos| self.window_pos_to_component_pos(window_pos, rect));
+		}
+		let instrument_count = project.instruments.len();
+		response.context_menu(|ui| {
+			match self
+				.context_menu_target
+				.and_then(|component_pos| project.components.get_mut(&component_pos))
+			{
+				Some(component) => {
+					let before = *component;
+					component.edit(ui, instrument_count);
+					self.modified |= *component != before;
+				}
+				None => ui.close_menu(),
+			}
+		});
 	}
 
 	fn draw_pellet(&self, painter: &Painter, position: Vec2, region: Rect) {
@@ -151,6 +185,37 @@ impl Editor {
 		}
 	}
 
+	fn draw_portal_links(&self, painter: &Painter, project: &Project, region: Rect) {
+		let mut channels: HashMap<u8, Vec<ComponentPos>> = HashMap::new();
+		for (&position, component) in &project.components {
+			if let Component::Portal { channel } = component {
+				channels.entry(*channel).or_default().push(position);
+			}
+		}
+
+		let color = painter
+			.ctx()
+			.style()
+			.visuals
+			.weak_text_color()
+			.linear_multiply(0.3);
+		let stroke = Stroke::new(self.component_size() * 0.05, color);
+
+		for portals in channels.values() {
+			for (i, &from) in portals.iter().enumerate() {
+				for &to in &portals[i + 1..] {
+					painter.line_segment(
+						[
+							self.component_pos_to_window_pos(from, region).center(),
+							self.component_pos_to_window_pos(to, region).center(),
+						],
+						stroke,
+					);
+				}
+			}
+		}
+	}
+
 	fn origin(&self, region: Rect) -> Pos2 {
 		region.center() + (self.position * self.zoom)
 	}
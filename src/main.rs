@@ -9,13 +9,10 @@
 	non_ascii_idents,
 	nonstandard_style,
 	noop_method_call,
-	pointer_structural_match,
-	private_in_public,
 	rust_2018_idioms,
 	unused_qualifications
 )]
 #![warn(clippy::pedantic)]
-#![allow(clippy::let_underscore_drop)]
 #![forbid(unsafe_code)]
 
 use eframe::{run_native, NativeOptions};
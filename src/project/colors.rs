@@ -6,12 +6,8 @@ use epaint::Color32;
 pub enum Category {
 	Emitter,
 	Routing,
-	Drum,
 	Instrument,
-	Tone,
 	Scale,
-	Arpeggio,
-	Tuning,
 	Step,
 	Debug,
 }
@@ -37,36 +33,16 @@ impl Category {
 				Color32::from_rgb(0xf5, 0xcd, 0x79),
 				Color32::from_rgb(0xf7, 0xd7, 0x94),
 			),
-			// blue
-			Self::Drum => (
-				Color32::from_rgb(0x54, 0x6d, 0xe5),
-				Color32::from_rgb(0xf3, 0xa6, 0x83),
-			),
 			// purple
 			Self::Instrument => (
 				Color32::from_rgb(0x57, 0x4b, 0x90),
 				Color32::from_rgb(0x78, 0x6f, 0xa6),
 			),
-			// cyan
-			Self::Tone => (
-				Color32::from_rgb(0x3d, 0xc1, 0xd3),
-				Color32::from_rgb(0x63, 0xcd, 0xda),
-			),
 			// pink
 			Self::Scale => (
 				Color32::from_rgb(0xf7, 0x8f, 0xb3),
 				Color32::from_rgb(0xf8, 0xa5, 0xc2),
 			),
-			// dark pink
-			Self::Arpeggio => (
-				Color32::from_rgb(0xc4, 0x45, 0x69),
-				Color32::from_rgb(0xcf, 0x6a, 0x87),
-			),
-			// orange
-			Self::Tuning => (
-				Color32::from_rgb(0xf1, 0x90, 0x66),
-				Color32::from_rgb(0xf3, 0xa6, 0x83),
-			),
 			// gray
 			Self::Step => (
 				Color32::from_rgb(0x30, 0x39, 0x52),
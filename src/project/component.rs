@@ -1,21 +1,40 @@
-use std::f32::consts::TAU;
+use std::f32::consts::{PI, TAU};
+use std::ops::RangeInclusive;
 
-use egui::{Align2, Color32, Painter, Rect, Stroke, Vec2};
+use egui::{Align2, Color32, DragValue, Painter, Rect, Slider, Stroke, Ui, Vec2};
 use enumset::EnumSet;
 use epaint::{CubicBezierShape, PathShape};
 use serde::{Deserialize, Serialize};
 
 use super::colors::{Category, Palette};
-use super::{Direction, Pellet};
-use crate::sound::{Pitch, Sound, Type as SoundType};
+use super::direction::Diagonal;
+use super::random::Rng;
+use super::{BeatsPerMinute, Direction, Pellet};
+use crate::sound::{Pitch, Sound, Type as SoundType, Velocity};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "_type")]
 pub enum Component {
 	Emitter {
 		direction: Direction,
 	},
 	RightTurn,
+	LeftTurn,
+	UTurn,
+	Splitter {
+		/// Also emit in the incoming direction, not just to either side.
+		#[serde(default)]
+		straight: bool,
+	},
+	Mirror {
+		diagonal: Diagonal,
+	},
+	OneWay {
+		direction: Direction,
+	},
+	Portal {
+		channel: u8,
+	},
 	Alternator {
 		#[serde(default = "default_direction")]
 		current_direction: Direction,
@@ -23,15 +42,122 @@ pub enum Component {
 	Debug,
 	IncrementPitch {
 		#[serde(default)]
-		current: u8,
+		current: Pitch,
 	},
 	Guitar,
 	Consumer,
 	Half {
 		state: bool,
 	},
+	Counter {
+		every: u8,
+		#[serde(default)]
+		offset: u8,
+		#[serde(default)]
+		count: u8,
+	},
+	Switch {
+		every: u8,
+		#[serde(default)]
+		count: u8,
+	},
+	Chance {
+		probability: f32,
+	},
+	RandomDirection,
+	RandomPitch {
+		low: Pitch,
+		high: Pitch,
+	},
+	Gate {
+		kind: GateKind,
+		window: Window,
+		output: Direction,
+		#[serde(skip)]
+		pending: Option<Arrivals>,
+	},
+	Latch {
+		set: Direction,
+		reset: Direction,
+		#[serde(default)]
+		on: bool,
+	},
+	/// Changes the tempo to `tempo` until playback is stopped.
+	SetTempo {
+		tempo: BeatsPerMinute,
+	},
+	/// Multiplies the tempo by `factor` until playback is stopped.
+	ScaleTempo {
+		factor: f32,
+	},
+	/// Plays one of the project's own instruments, or nothing if it doesn't have that many.
+	Instrument {
+		instrument: u8,
+	},
+	/// Makes pellets louder by raising their velocity by `amount`.
+	Accent {
+		amount: u8,
+	},
+	/// Makes pellets quieter by lowering their velocity by `amount`.
+	Attenuate {
+		amount: u8,
+	},
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GateKind {
+	/// Emit as soon as pellets have arrived from two different sides within the window.
+	And,
+	/// Emit at the end of the window if pellets arrived from exactly one side.
+	Xor,
+}
+
+/// How close together pellets must arrive at a gate to count as coinciding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Window {
+	Tick,
+	Beat,
 }
 
+impl Window {
+	fn index(self, time: Time) -> u64 {
+		match self {
+			Self::Tick => time.tick,
+			Self::Beat => time.beat,
+		}
+	}
+}
+
+/// The pellets that have reached a gate during one window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arrivals {
+	window_index: u64,
+	/// The directions the pellets were travelling in.
+	directions: EnumSet<Direction>,
+	pitch: Pitch,
+	velocity: Velocity,
+}
+
+/// How far playback has progressed since it was last stopped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Time {
+	pub tick: u64,
+	pub beat: u64,
+}
+
+/// Everything besides the pellet itself that a component may need in order to react to it.
+pub struct Context<'a> {
+	pub rng: &'a mut Rng,
+	pub time: Time,
+	/// The tempo playback is currently running at.
+	pub tempo: &'a mut BeatsPerMinute,
+}
+
+/// The factors a `ScaleTempo` component can have.
+pub const TEMPO_FACTORS: RangeInclusive<f32> = 0.1..=10.0;
+
 const fn default_direction() -> Direction {
 	Direction::Up
 }
@@ -39,7 +165,10 @@ const fn default_direction() -> Direction {
 pub struct ShouldEmit {
 	pub sound: Option<Sound>,
 	pub pitch: Pitch,
+	pub velocity: Velocity,
 	pub directions: EnumSet<Direction>,
+	/// Send the pellet out of every other portal on this channel.
+	pub teleport: Option<u8>,
 }
 
 impl ShouldEmit {
@@ -47,31 +176,143 @@ impl ShouldEmit {
 		Self {
 			sound: Some(sound),
 			pitch: Pitch::new(0),
+			velocity: Velocity::DEFAULT,
 			directions: EnumSet::empty(),
+			teleport: None,
 		}
 	}
 }
 
 impl Component {
+	/// The `_type` of every kind of component in project files.
+	pub const TYPES: &[&str] = &[
+		"emitter",
+		"right_turn",
+		"left_turn",
+		"u_turn",
+		"splitter",
+		"mirror",
+		"one_way",
+		"portal",
+		"alternator",
+		"debug",
+		"increment_pitch",
+		"guitar",
+		"consumer",
+		"half",
+		"counter",
+		"switch",
+		"chance",
+		"random_direction",
+		"random_pitch",
+		"gate",
+		"latch",
+		"set_tempo",
+		"scale_tempo",
+		"instrument",
+		"accent",
+		"attenuate",
+	];
+
 	pub const PALETTE_LIST: &[Self] = &[
 		Self::Emitter {
 			direction: Direction::Up,
 		},
 		Self::Consumer,
 		Self::RightTurn,
+		Self::LeftTurn,
+		Self::UTurn,
+		Self::Splitter { straight: false },
+		Self::Splitter { straight: true },
+		Self::Mirror {
+			diagonal: Diagonal::Rising,
+		},
+		Self::Mirror {
+			diagonal: Diagonal::Falling,
+		},
+		Self::OneWay {
+			direction: Direction::Up,
+		},
+		Self::Portal { channel: 0 },
 		Self::Alternator {
 			current_direction: default_direction(),
 		},
-		Self::IncrementPitch { current: 0 },
+		Self::IncrementPitch {
+			current: Pitch::new(0),
+		},
 		Self::Guitar,
 		Self::Half { state: false },
+		Self::Counter {
+			every: 4,
+			offset: 0,
+			count: 0,
+		},
+		Self::Switch { every: 4, count: 0 },
+		Self::Chance { probability: 0.5 },
+		Self::RandomDirection,
+		Self::RandomPitch {
+			low: Pitch::new(0),
+			high: Pitch::new(Pitch::MAX),
+		},
+		Self::Gate {
+			kind: GateKind::And,
+			window: Window::Tick,
+			output: Direction::Up,
+			pending: None,
+		},
+		Self::Gate {
+			kind: GateKind::Xor,
+			window: Window::Tick,
+			output: Direction::Up,
+			pending: None,
+		},
+		Self::Latch {
+			set: Direction::Right,
+			reset: Direction::Left,
+			on: false,
+		},
+		Self::SetTempo {
+			tempo: BeatsPerMinute(120.0),
+		},
+		Self::ScaleTempo { factor: 1.1 },
+		Self::Instrument { instrument: 0 },
+		Self::Accent { amount: 16 },
+		Self::Attenuate { amount: 16 },
 	];
 
-	pub fn on_pellet(&mut self, pellet: Pellet) -> ShouldEmit {
+	#[allow(clippy::too_many_lines)] // it's just a big match
+	pub fn on_pellet(&mut self, pellet: Pellet, context: &mut Context<'_>) -> ShouldEmit {
 		let directions = match self {
-			Self::Emitter { .. } => EnumSet::empty(),
-			Self::Consumer { .. } => EnumSet::empty(),
+			Self::Emitter { .. } | Self::Consumer => EnumSet::empty(),
 			Self::RightTurn => EnumSet::only(pellet.direction().rotate90()),
+			Self::LeftTurn => EnumSet::only(pellet.direction().rotate270()),
+			Self::UTurn => EnumSet::only(pellet.direction().flip()),
+			Self::Splitter { straight } => {
+				let incoming = pellet.direction();
+				let sides = incoming.rotate90() | incoming.rotate270();
+				if *straight {
+					sides | incoming
+				} else {
+					sides
+				}
+			}
+			Self::Mirror { diagonal } => EnumSet::only(diagonal.reflect(pellet.direction())),
+			Self::OneWay { direction } => {
+				if pellet.direction() == *direction {
+					EnumSet::only(*direction)
+				} else {
+					EnumSet::empty()
+				}
+			}
+			Self::Portal { channel } => {
+				return ShouldEmit {
+					sound: None,
+					pitch: pellet.pitch,
+					velocity: pellet.velocity,
+					directions: EnumSet::empty(),
+					teleport: Some(*channel),
+				}
+			}
 			Self::Alternator { current_direction } => {
 				*current_direction = current_direction.rotate90();
 				if *current_direction == pellet.direction().flip() {
@@ -86,18 +327,28 @@ impl Component {
 			Self::IncrementPitch { current } => {
 				let ret = ShouldEmit {
 					sound: None,
-					pitch: pellet.pitch.increment_by(*current),
+					pitch: pellet.pitch.increment_by(current.semitones()),
+					velocity: pellet.velocity,
 					directions: EnumSet::only(pellet.direction()),
+					teleport: None,
 				};
-				*current = (*current + 1) % (Pitch::MAX + 1);
+				*current = current.increment_by(1);
 				return ret;
 			}
 			Self::Guitar => {
 				return ShouldEmit::sound(Sound {
 					pitch: pellet.pitch,
+					velocity: pellet.velocity,
 					ty: SoundType::Guitar,
 				})
 			}
+			Self::Instrument { instrument } => {
+				return ShouldEmit::sound(Sound {
+					pitch: pellet.pitch,
+					velocity: pellet.velocity,
+					ty: SoundType::Custom(*instrument),
+				})
+			}
 			Self::Half { state } => {
 				*state = !*state;
 				if *state {
@@ -106,12 +357,163 @@ impl Component {
 					EnumSet::empty()
 				}
 			}
+			Self::Counter {
+				every,
+				offset,
+				count,
+			} => {
+				let every = (*every).max(1);
+				let passes = *count == *offset % every;
+				// `count` can be as high as `every`, so reduce it first so that adding one can't overflow
+				*count = (*count % every + 1) % every;
+				if passes {
+					EnumSet::only(pellet.direction())
+				} else {
+					EnumSet::empty()
+				}
+			}
+			Self::Switch { every, count } => {
+				*count = count.saturating_add(1);
+				if *count >= *every {
+					*count = 0;
+					EnumSet::only(pellet.direction().rotate270())
+				} else {
+					EnumSet::only(pellet.direction())
+				}
+			}
+			Self::Chance { probability } => {
+				if context.rng.next_f32() < *probability {
+					EnumSet::only(pellet.direction())
+				} else {
+					EnumSet::empty()
+				}
+			}
+			Self::RandomDirection => {
+				let incoming = pellet.direction();
+				let choices = [incoming.rotate270(), incoming, incoming.rotate90()];
+				EnumSet::only(choices[usize::from(context.rng.below(3))])
+			}
+			Self::Gate {
+				kind,
+				window,
+				output,
+				pending,
+			} => {
+				let window_index = window.index(context.time);
+				let arrivals = match pending {
+					Some(arrivals) if arrivals.window_index == window_index => arrivals,
+					_ => pending.insert(Arrivals {
+						window_index,
+						directions: EnumSet::empty(),
+						pitch: pellet.pitch,
+						velocity: pellet.velocity,
+					}),
+				};
+				arrivals.directions |= pellet.direction();
+				arrivals.pitch = pellet.pitch;
+				arrivals.velocity = pellet.velocity;
+				if *kind == GateKind::And && arrivals.directions.len() >= 2 {
+					*pending = None;
+					EnumSet::only(*output)
+				} else {
+					EnumSet::empty()
+				}
+			}
+			Self::Latch { set, reset, on } => {
+				if pellet.direction() == *set {
+					*on = true;
+					EnumSet::empty()
+				} else if pellet.direction() == *reset {
+					*on = false;
+					EnumSet::empty()
+				} else if *on {
+					EnumSet::only(pellet.direction())
+				} else {
+					EnumSet::empty()
+				}
+			}
+			Self::SetTempo { tempo } => {
+				*context.tempo = tempo.clamp();
+				EnumSet::only(pellet.direction())
+			}
+			Self::ScaleTempo { factor } => {
+				*context.tempo = BeatsPerMinute(context.tempo.0 * *factor).clamp();
+				EnumSet::only(pellet.direction())
+			}
+			Self::Accent { amount } => {
+				return ShouldEmit {
+					sound: None,
+					pitch: pellet.pitch,
+					velocity: pellet.velocity.raised_by(*amount),
+					directions: EnumSet::only(pellet.direction()),
+					teleport: None,
+				};
+			}
+			Self::Attenuate { amount } => {
+				return ShouldEmit {
+					sound: None,
+					pitch: pellet.pitch,
+					velocity: pellet.velocity.lowered_by(*amount),
+					directions: EnumSet::only(pellet.direction()),
+					teleport: None,
+				};
+			}
+			Self::RandomPitch { low, high } => {
+				let low = low.semitones().min(high.semitones());
+				let high = high.semitones().max(low);
+				return ShouldEmit {
+					sound: None,
+					pitch: Pitch::new(low + context.rng.below(high - low + 1)),
+					velocity: pellet.velocity,
+					directions: EnumSet::only(pellet.direction()),
+					teleport: None,
+				};
+			}
 		};
 
 		ShouldEmit {
 			sound: None,
 			pitch: pellet.pitch,
+			velocity: pellet.velocity,
 			directions,
+			teleport: None,
+		}
+	}
+
+	/// Puts the component back into its initial state, as when playback is stopped.
+	pub fn reset(&mut self) {
+		match self {
+			Self::Alternator { current_direction } => *current_direction = default_direction(),
+			Self::IncrementPitch { current } => *current = Pitch::new(0),
+			Self::Half { state } => *state = false,
+			Self::Counter { count, .. } | Self::Switch { count, .. } => *count = 0,
+			Self::Gate { pending, .. } => *pending = None,
+			Self::Latch { on, .. } => *on = false,
+			_ => {}
+		}
+	}
+
+	/// Called once per tick, before any pellets are handled. Returns the direction, pitch and velocity of a pellet to emit, if any.
+	pub fn on_tick(&mut self, time: Time) -> Option<(Direction, Pitch, Velocity)> {
+		match self {
+			Self::Gate {
+				kind,
+				window,
+				output,
+				pending,
+			} => {
+				let arrivals = (*pending)?;
+				if arrivals.window_index == window.index(time) {
+					return None;
+				}
+				*pending = None;
+				(*kind == GateKind::Xor && arrivals.directions.len() == 1).then_some((
+					*output,
+					arrivals.pitch,
+					arrivals.velocity,
+				))
+			}
+			_ => None,
 		}
 	}
 
@@ -124,16 +526,34 @@ impl Component {
 
 	pub fn category(self) -> Category {
 		match self {
-			Component::Alternator { .. } | Component::Half { .. } | Component::RightTurn => {
-				Category::Routing
-			}
+			Component::Alternator { .. }
+			| Component::Half { .. }
+			| Component::RightTurn
+			| Component::LeftTurn
+			| Component::UTurn
+			| Component::Splitter { .. }
+			| Component::Mirror { .. }
+			| Component::OneWay { .. }
+			| Component::Portal { .. }
+			| Component::Chance { .. }
+			| Component::RandomDirection
+			| Component::Gate { .. }
+			| Component::Latch { .. } => Category::Routing,
+			Component::Counter { .. }
+			| Component::Switch { .. }
+			| Component::SetTempo { .. }
+			| Component::ScaleTempo { .. } => Category::Step,
 			Component::Debug => Category::Debug,
-			Component::IncrementPitch { .. } => Category::Scale,
-			Component::Guitar => Category::Instrument,
+			Component::IncrementPitch { .. }
+			| Component::RandomPitch { .. }
+			| Component::Accent { .. }
+			| Component::Attenuate { .. } => Category::Scale,
+			Component::Guitar | Component::Instrument { .. } => Category::Instrument,
 			Component::Consumer | Component::Emitter { .. } => Category::Emitter,
 		}
 	}
 
+	#[allow(clippy::too_many_lines)] // it's just a big match
 	pub fn draw(self, painter: &Painter, window_pos: Rect) {
 		const MAIN_SIZE_FACTOR: f32 = 0.9;
 		let main_size = window_pos.height() * MAIN_SIZE_FACTOR;
@@ -189,8 +609,73 @@ impl Component {
 					stroke,
 				));
 			}
+			Self::LeftTurn => {
+				let stroke = Stroke::new(main_size * 0.2, foreground);
+				painter.add(PathShape::line(
+					vec![
+						center + Vec2::DOWN * offset,
+						center,
+						center + Vec2::LEFT * offset,
+					],
+					stroke,
+				));
+			}
+			Self::UTurn => {
+				let stroke = Stroke::new(main_size * 0.2, foreground);
+				let radius = offset * 0.6;
+				let arc_center = center + Vec2::DOWN * offset * 0.2;
+				let mut points = vec![center + Vec2::new(-radius, -offset)];
+				points.extend((0..=8).map(|step| {
+					let angle = PI * az::cast::<_, f32>(step) / 8.0;
+					arc_center + Vec2::new(-radius * angle.cos(), radius * angle.sin())
+				}));
+				points.push(center + Vec2::new(radius, -offset));
+				painter.add(PathShape::line(points, stroke));
+			}
+			Self::Splitter { straight } => {
+				let stroke = Stroke::new(main_size * 0.2, foreground);
+				painter.line_segment(
+					[center + Vec2::LEFT * offset, center + Vec2::RIGHT * offset],
+					stroke,
+				);
+				let end = if straight {
+					Vec2::UP * offset
+				} else {
+					Vec2::ZERO
+				};
+				painter.line_segment([center + Vec2::DOWN * offset, center + end], stroke);
+			}
+			Self::Mirror { diagonal } => {
+				let stroke = Stroke::new(main_size * 0.2, foreground);
+				let offset = offset / std::f32::consts::SQRT_2;
+				let corner = match diagonal {
+					Diagonal::Rising => Vec2::new(offset, -offset),
+					Diagonal::Falling => Vec2::new(offset, offset),
+				};
+				painter.line_segment([center - corner, center + corner], stroke);
+			}
+			Self::OneWay { direction } => {
+				let stroke = Stroke::new(main_size * 0.15, foreground);
+				let forward = direction.as_vec2() * offset;
+				let side = direction.rotate90().as_vec2() * offset * 0.6;
+				painter.line_segment([center - forward, center + forward], stroke);
+				painter.add(PathShape::line(
+					vec![center + side, center + forward, center - side],
+					stroke,
+				));
+			}
+			Self::Portal { channel } => {
+				painter.circle_stroke(center, offset, (main_size * 0.1, foreground));
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					channel.to_string(),
+					epaint::FontId::proportional(main_size * 0.45),
+					foreground,
+				);
+			}
 			Self::Alternator { .. } => {
-				painter.add(epaint::PathShape::convex_polygon(
+				painter.add(PathShape::convex_polygon(
 					[
 						Direction::Up,
 						Direction::Right,
@@ -207,7 +692,7 @@ impl Component {
 			Self::Debug => {}
 			Self::IncrementPitch { .. } => {
 				let center = center + Vec2::DOWN * main_size * 0.03;
-				painter.add(epaint::PathShape::convex_polygon(
+				painter.add(PathShape::convex_polygon(
 					vec![
 						center + Vec2::angled(TAU * -0.25) * offset,
 						center + Vec2::angled(TAU * (1.0 / 3.0 - 0.25)) * offset,
@@ -242,6 +727,145 @@ impl Component {
 					stroke: Stroke::none(),
 				});
 			}
+			Self::Counter { every, .. } => {
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					format!("1/{every}"),
+					epaint::FontId::proportional(main_size * 0.4),
+					foreground,
+				);
+			}
+			Self::Switch { every, .. } => {
+				let stroke = Stroke::new(main_size * 0.1, foreground);
+				painter.add(PathShape::line(
+					vec![
+						center + Vec2::DOWN * offset,
+						center,
+						center + Vec2::UP * offset,
+					],
+					stroke,
+				));
+				painter.line_segment([center, center + Vec2::LEFT * offset], stroke);
+				painter.text(
+					center + Vec2::new(offset, offset) * 0.5,
+					Align2::CENTER_CENTER,
+					every.to_string(),
+					epaint::FontId::proportional(main_size * 0.35),
+					foreground,
+				);
+			}
+			Self::Chance { probability } => {
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					format!("{:.0}%", probability * 100.0),
+					epaint::FontId::proportional(main_size * 0.35),
+					foreground,
+				);
+			}
+			Self::RandomDirection => {
+				let half = offset * 0.8;
+				painter.rect_stroke(
+					Rect::from_center_size(center, Vec2::splat(half * 2.0)),
+					main_size * 0.08,
+					(main_size * 0.08, foreground),
+				);
+				for pip in [-0.5, 0.0, 0.5] {
+					painter.circle_filled(
+						center + Vec2::splat(pip * half),
+						main_size * 0.06,
+						foreground,
+					);
+				}
+			}
+			Self::Gate { kind, output, .. } => {
+				let stroke = Stroke::new(main_size * 0.08, foreground);
+				painter.line_segment(
+					[
+						center + output.as_vec2() * offset * 0.6,
+						center + output.as_vec2() * offset,
+					],
+					stroke,
+				);
+				let label = match kind {
+					GateKind::And => "AND",
+					GateKind::Xor => "XOR",
+				};
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					label,
+					epaint::FontId::proportional(main_size * 0.3),
+					foreground,
+				);
+			}
+			Self::Latch { on, .. } => {
+				let stroke = Stroke::new(main_size * 0.08, foreground);
+				let rect = Rect::from_center_size(center, Vec2::splat(offset * 1.4));
+				if on {
+					painter.rect_filled(rect, main_size * 0.05, foreground);
+				} else {
+					painter.rect_stroke(rect, main_size * 0.05, stroke);
+				}
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					"SR",
+					epaint::FontId::proportional(main_size * 0.3),
+					if on { background } else { foreground },
+				);
+			}
+			Self::RandomPitch { .. } => {
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					"?",
+					epaint::FontId::proportional(main_size * 0.8),
+					foreground,
+				);
+			}
+			Self::SetTempo { tempo } => {
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					format!("{:.0}", tempo.0),
+					epaint::FontId::proportional(main_size * 0.35),
+					foreground,
+				);
+			}
+			Self::Instrument { instrument } => {
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					format!("♪{instrument}"),
+					epaint::FontId::proportional(main_size * 0.35),
+					foreground,
+				);
+			}
+			Self::ScaleTempo { factor } => {
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					format!("×{factor:.2}"),
+					epaint::FontId::proportional(main_size * 0.3),
+					foreground,
+				);
+			}
+			Self::Accent { amount } | Self::Attenuate { amount } => {
+				let sign = if matches!(self, Self::Accent { .. }) {
+					'+'
+				} else {
+					'−'
+				};
+				painter.text(
+					center,
+					Align2::CENTER_CENTER,
+					format!("{sign}{amount}"),
+					epaint::FontId::proportional(main_size * 0.35),
+					foreground,
+				);
+			}
 		}
 	}
 
@@ -253,8 +877,318 @@ impl Component {
 			Self::Guitar => "Guitar",
 			Self::Debug => "Super Secret Debug Component",
 			Self::RightTurn => "Right Turn",
+			Self::LeftTurn => "Left Turn",
+			Self::UTurn => "U-Turn",
+			Self::Splitter { straight: false } => "Splitter",
+			Self::Splitter { straight: true } => "Three-Way Splitter",
+			Self::Mirror { .. } => "Mirror",
+			Self::OneWay { .. } => "One-Way Gate",
+			Self::Portal { .. } => "Portal",
 			Self::IncrementPitch { .. } => "Rising Pitch",
 			Self::Half { .. } => "Half",
+			Self::Counter { .. } => "Counter Gate",
+			Self::Switch { .. } => "Nth Switch",
+			Self::Chance { .. } => "Chance",
+			Self::RandomDirection => "Random Direction",
+			Self::RandomPitch { .. } => "Random Pitch",
+			Self::Gate {
+				kind: GateKind::And,
+				..
+			} => "AND Gate",
+			Self::Gate {
+				kind: GateKind::Xor,
+				..
+			} => "XOR Gate",
+			Self::Latch { .. } => "Latch",
+			Self::SetTempo { .. } => "Set Tempo",
+			Self::ScaleTempo { .. } => "Scale Tempo",
+			Self::Instrument { .. } => "Instrument",
+			Self::Accent { .. } => "Accent",
+			Self::Attenuate { .. } => "Attenuate",
+		}
+	}
+
+	/// Shows controls for the component's settings in `ui`. `instrument_count` is how many custom instruments the project has.
+	#[allow(clippy::too_many_lines)] // it's just a big match
+	pub fn edit(&mut self, ui: &mut Ui, instrument_count: usize) {
+		ui.label(self.name());
+		match self {
+			Self::Emitter { direction } | Self::OneWay { direction } => direction_picker(ui, direction),
+			Self::Gate {
+				kind,
+				window,
+				output,
+				..
+			} => {
+				ui.horizontal(|ui| {
+					ui.selectable_value(kind, GateKind::And, "AND");
+					ui.selectable_value(kind, GateKind::Xor, "XOR");
+				});
+				ui.horizontal(|ui| {
+					ui.label("Window");
+					ui.selectable_value(window, Window::Tick, "Tick");
+					ui.selectable_value(window, Window::Beat, "Beat");
+				});
+				ui.label("Output");
+				direction_picker(ui, output);
+			}
+			Self::Latch { set, reset, .. } => {
+				ui.label("Set by pellets going");
+				direction_picker(ui, set);
+				ui.label("Reset by pellets going");
+				direction_picker(ui, reset);
+			}
+			Self::Splitter { straight } => {
+				ui.checkbox(straight, "Also emit straight ahead");
+			}
+			Self::Portal { channel } => {
+				ui.add(DragValue::new(channel).prefix("Channel "));
+			}
+			Self::Counter { every, offset, .. } => {
+				ui.add(
+					DragValue::new(every)
+						.prefix("Pass 1 of every ")
+						.clamp_range(1..=u8::MAX),
+				);
+				ui.add(
+					DragValue::new(offset)
+						.prefix("Offset ")
+						.clamp_range(0..=every.saturating_sub(1)),
+				);
+			}
+			Self::Switch { every, .. } => {
+				ui.add(
+					DragValue::new(every)
+						.prefix("Turn left every ")
+						.clamp_range(1..=u8::MAX),
+				);
+			}
+			Self::Chance { probability } => {
+				ui.add(Slider::new(probability, 0.0..=1.0).text("Probability"));
+			}
+			Self::SetTempo { tempo } => {
+				ui.add(
+					DragValue::new(&mut tempo.0)
+						.suffix(" BPM")
+						.clamp_range(BeatsPerMinute::MIN.0..=BeatsPerMinute::MAX.0),
+				);
+			}
+			Self::Instrument { instrument } => {
+				let last = az::saturating_cast::<_, u8>(instrument_count.saturating_sub(1));
+				ui.add(
+					DragValue::new(instrument)
+						.prefix("Instrument ")
+						.clamp_range(0..=last),
+				);
+			}
+			Self::Accent { amount } => {
+				ui.add(
+					DragValue::new(amount)
+						.prefix("Raise velocity by ")
+						.clamp_range(0..=Velocity::MAX),
+				);
+			}
+			Self::Attenuate { amount } => {
+				ui.add(
+					DragValue::new(amount)
+						.prefix("Lower velocity by ")
+						.clamp_range(0..=Velocity::MAX),
+				);
+			}
+			Self::ScaleTempo { factor } => {
+				ui.add(
+					DragValue::new(factor)
+						.prefix("×")
+						.speed(0.01)
+						.clamp_range(TEMPO_FACTORS),
+				);
+			}
+			Self::RandomPitch { low, high } => {
+				pitch_picker(ui, "Lowest ", low);
+				pitch_picker(ui, "Highest ", high);
+			}
+			Self::Mirror { diagonal } => {
+				ui.horizontal(|ui| {
+					ui.selectable_value(diagonal, Diagonal::Rising, "/");
+					ui.selectable_value(diagonal, Diagonal::Falling, "\\");
+				});
+			}
+			Self::RightTurn
+			| Self::LeftTurn
+			| Self::UTurn
+			| Self::Alternator { .. }
+			| Self::Debug
+			| Self::IncrementPitch { .. }
+			| Self::Guitar
+			| Self::Consumer
+			| Self::Half { .. }
+			| Self::RandomDirection => {
+				ui.weak("No settings");
+			}
 		}
 	}
 }
+
+fn direction_picker(ui: &mut Ui, direction: &mut Direction) {
+	ui.horizontal(|ui| {
+		for option in EnumSet::<Direction>::all() {
+			ui.selectable_value(direction, option, option.name());
+		}
+	});
+}
+
+fn pitch_picker(ui: &mut Ui, prefix: &str, pitch: &mut Pitch) {
+	let mut semitones = pitch.semitones();
+	if ui
+		.add(
+			DragValue::new(&mut semitones)
+				.prefix(prefix)
+				.clamp_range(0..=Pitch::MAX),
+		)
+		.changed()
+	{
+		*pitch = Pitch::new(semitones.min(Pitch::MAX));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::project::Position;
+
+	/// Sends a pellet travelling in `direction` into `component` at `tick`, returning where it goes.
+	fn send(component: &mut Component, direction: Direction, tick: u64) -> EnumSet<Direction> {
+		let pellet = Pellet::new_at(Position { x: 0, y: 0 }, direction);
+		let mut context = Context {
+			rng: &mut Rng::new(0),
+			time: Time { tick, beat: 0 },
+			tempo: &mut BeatsPerMinute(120.0),
+		};
+		component.on_pellet(pellet, &mut context).directions
+	}
+
+	/// Which of `count` pellets in a row get through `component`.
+	fn passes(mut component: Component, count: usize) -> Vec<bool> {
+		(0..count)
+			.map(|_| !send(&mut component, Direction::Up, 0).is_empty())
+			.collect()
+	}
+
+	#[test]
+	fn counter_passes_every_nth_pellet_from_its_offset() {
+		let counter = Component::Counter {
+			every: 3,
+			offset: 1,
+			count: 0,
+		};
+		assert_eq!(passes(counter, 6), [false, true, false, false, true, false]);
+
+		let counter = Component::Counter {
+			every: 255,
+			offset: 0,
+			count: 255,
+		};
+		assert_eq!(passes(counter, 2), [false, false]);
+		let counter = Component::Counter {
+			every: 1,
+			offset: 0,
+			count: 0,
+		};
+		assert_eq!(passes(counter, 3), [true, true, true]);
+	}
+
+	#[test]
+	fn switch_turns_every_nth_pellet_left() {
+		let mut switch = Component::Switch { every: 3, count: 0 };
+		let turns: Vec<_> = (0..6)
+			.map(|_| send(&mut switch, Direction::Up, 0) == EnumSet::only(Direction::Left))
+			.collect();
+		assert_eq!(turns, [false, false, true, false, false, true]);
+
+		let mut switch = Component::Switch {
+			every: 255,
+			count: 255,
+		};
+		assert_eq!(send(&mut switch, Direction::Up, 0), Direction::Left);
+		assert_eq!(send(&mut switch, Direction::Up, 0), Direction::Up);
+	}
+
+	#[test]
+	fn types_are_the_serialized_tags() {
+		let mut tags: Vec<String> = Component::PALETTE_LIST
+			.iter()
+			.chain([&Component::Debug])
+			.map(|component| {
+				serde_json::to_value(component).unwrap()["_type"]
+					.as_str()
+					.unwrap()
+					.to_owned()
+			})
+			.collect();
+		tags.sort_unstable();
+		tags.dedup();
+		let mut types = Component::TYPES.to_vec();
+		types.sort_unstable();
+		assert_eq!(tags, types);
+	}
+
+	fn gate(kind: GateKind, window: Window) -> Component {
+		Component::Gate {
+			kind,
+			window,
+			output: Direction::Up,
+			pending: None,
+		}
+	}
+
+	/// Whether the gate emits at the start of `tick`.
+	fn fires_on_tick(gate: &mut Component, tick: u64) -> bool {
+		gate.on_tick(Time { tick, beat: 0 }).is_some()
+	}
+
+	#[test]
+	fn and_gate_fires_when_both_sides_arrive_in_one_window() {
+		let mut and = gate(GateKind::And, Window::Tick);
+		assert!(send(&mut and, Direction::Right, 5).is_empty());
+		assert_eq!(send(&mut and, Direction::Left, 5), Direction::Up);
+		assert!(!fires_on_tick(&mut and, 6));
+
+		// a tick apart is too late
+		assert!(send(&mut and, Direction::Right, 7).is_empty());
+		assert!(!fires_on_tick(&mut and, 8));
+		assert!(send(&mut and, Direction::Left, 8).is_empty());
+
+		// from the same side twice isn't enough
+		let mut and = gate(GateKind::And, Window::Tick);
+		assert!(send(&mut and, Direction::Right, 5).is_empty());
+		assert!(send(&mut and, Direction::Right, 5).is_empty());
+	}
+
+	#[test]
+	fn xor_gate_fires_after_a_window_with_one_side() {
+		let mut xor = gate(GateKind::Xor, Window::Tick);
+		assert!(send(&mut xor, Direction::Right, 5).is_empty());
+		assert!(!fires_on_tick(&mut xor, 5));
+		assert!(fires_on_tick(&mut xor, 6));
+		assert!(!fires_on_tick(&mut xor, 7));
+
+		assert!(send(&mut xor, Direction::Right, 8).is_empty());
+		assert!(send(&mut xor, Direction::Left, 8).is_empty());
+		assert!(!fires_on_tick(&mut xor, 9));
+	}
+
+	#[test]
+	fn latch_passes_pellets_between_set_and_reset() {
+		let mut latch = Component::Latch {
+			set: Direction::Right,
+			reset: Direction::Left,
+			on: false,
+		};
+		assert!(send(&mut latch, Direction::Up, 0).is_empty());
+		assert!(send(&mut latch, Direction::Right, 0).is_empty());
+		assert_eq!(send(&mut latch, Direction::Up, 0), Direction::Up);
+		assert_eq!(send(&mut latch, Direction::Down, 0), Direction::Down);
+		assert!(send(&mut latch, Direction::Left, 0).is_empty());
+		assert!(send(&mut latch, Direction::Up, 0).is_empty());
+	}
+}
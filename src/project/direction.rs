@@ -37,6 +37,15 @@ impl Direction {
 		}
 	}
 
+	pub fn rotate270(self) -> Self {
+		match self {
+			Self::Up => Self::Left,
+			Self::Left => Self::Down,
+			Self::Down => Self::Right,
+			Self::Right => Self::Up,
+		}
+	}
+
 	pub fn flip(self) -> Self {
 		match self {
 			Self::Up => Self::Down,
@@ -53,6 +62,15 @@ impl Direction {
 		}
 	}
 
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Up => "Up",
+			Self::Right => "Right",
+			Self::Down => "Down",
+			Self::Left => "Left",
+		}
+	}
+
 	pub fn sign(self) -> f32 {
 		if self.negative() {
 			-1.0
@@ -61,3 +79,24 @@ impl Direction {
 		}
 	}
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Diagonal {
+	/// Bottom-left to top-right, like `/`.
+	Rising,
+	/// Top-left to bottom-right, like `\`.
+	Falling,
+}
+
+impl Diagonal {
+	/// The direction a pellet travelling in `incoming` leaves in after bouncing off a mirror along this diagonal.
+	pub fn reflect(self, incoming: Direction) -> Direction {
+		match (self, incoming) {
+			(Self::Rising, Direction::Right) | (Self::Falling, Direction::Left) => Direction::Up,
+			(Self::Rising, Direction::Left) | (Self::Falling, Direction::Right) => Direction::Down,
+			(Self::Rising, Direction::Up) | (Self::Falling, Direction::Down) => Direction::Right,
+			(Self::Rising, Direction::Down) | (Self::Falling, Direction::Up) => Direction::Left,
+		}
+	}
+}
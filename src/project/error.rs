@@ -0,0 +1,148 @@
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+use super::validate::InvalidValue;
+
+/// A place in a project file that an error refers to.
+#[derive(Debug)]
+pub struct Location {
+	/// 1-based.
+	pub line: usize,
+	/// 1-based.
+	pub column: usize,
+	/// The full text of the offending line.
+	pub source_line: String,
+}
+
+impl Location {
+	pub fn in_text(text: &str, line: usize, column: usize) -> Option<Self> {
+		let source_line = text.lines().nth(line.checked_sub(1)?)?;
+		Some(Self {
+			line,
+			column,
+			source_line: source_line.to_owned(),
+		})
+	}
+}
+
+#[derive(Debug)]
+pub enum Error {
+	Io {
+		path: PathBuf,
+		source: io::Error,
+	},
+	/// The file is not valid JSON or does not match the project structure.
+	Parse {
+		message: String,
+		location: Option<Location>,
+	},
+	UnknownComponent {
+		name: String,
+		location: Option<Location>,
+	},
+	/// Values that are out of range, such as pitches that are too high.
+	InvalidValues(Vec<InvalidValue>),
+	InvalidVersion(serde_json::Value),
+	/// The file was saved by a newer version of Hanlon.
+	NewerVersion {
+		version: u64,
+		supported: u64,
+	},
+	Migration {
+		from_version: u64,
+		message: String,
+	},
+	Serialize(serde_json::Error),
+	/// An instrument's sample file could not be decoded.
+	Sample {
+		path: PathBuf,
+		message: String,
+	},
+}
+
+impl Error {
+	pub fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+		let path = path.into();
+		|source| Self::Io { path, source }
+	}
+
+	/// Converts a deserialization error, using `text` to find the offending line if it is available.
+	pub fn from_json(error: &serde_json::Error, text: Option<&str>) -> Self {
+		let location = text.and_then(|text| Location::in_text(text, error.line(), error.column()));
+
+		// the error message includes the location, which we handle separately.
+		let mut message = error.to_string();
+		if error.line() != 0 {
+			let suffix = format!(" at line {} column {}", error.line(), error.column());
+			if message.ends_with(&suffix) {
+				message.truncate(message.len() - suffix.len());
+			}
+		}
+
+		Self::Parse { message, location }
+	}
+
+	pub fn location(&self) -> Option<&Location> {
+		match self {
+			Self::Parse { location, .. } | Self::UnknownComponent { location, .. } => location.as_ref(),
+			// only the first can be shown
+			Self::InvalidValues(invalid) => invalid.iter().find_map(|value| value.location.as_ref()),
+			Self::Io { .. }
+			| Self::InvalidVersion(..)
+			| Self::NewerVersion { .. }
+			| Self::Migration { .. }
+			| Self::Serialize(..)
+			| Self::Sample { .. } => None,
+		}
+	}
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Io { path, source } => write!(f, "could not access {}: {source}", path.display()),
+			Self::Parse { message, .. } => write!(f, "invalid project data: {message}"),
+			Self::UnknownComponent { name, .. } => write!(f, "unknown component type `{name}`"),
+			Self::InvalidValues(invalid) => {
+				write!(f, "{} invalid values:", invalid.len())?;
+				for value in invalid {
+					write!(f, "\n{value}")?;
+				}
+				Ok(())
+			}
+			Self::InvalidVersion(version) => write!(f, "invalid format version {version}"),
+			Self::NewerVersion { version, supported } => write!(
+				f,
+				"this project was saved by a newer version of Hanlon (format version {version}, but only up to {supported} is supported)"
+			),
+			Self::Migration {
+				from_version,
+				message,
+			} => write!(
+				f,
+				"could not upgrade project from format version {from_version}: {message}"
+			),
+			Self::Serialize(error) => write!(f, "could not serialize project: {error}"),
+			Self::Sample { path, message } => {
+				write!(f, "could not decode sample {}: {message}", path.display())
+			}
+		}
+	}
+}
+
+impl Display for Location {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(f, "line {}, column {}", self.line, self.column)
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Io { source, .. } => Some(source),
+			Self::Serialize(source) => Some(source),
+			_ => None,
+		}
+	}
+}
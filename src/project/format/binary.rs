@@ -0,0 +1,312 @@
+//! A compact binary project format for very large canvases.
+//!
+//! Generated projects can have hundreds of thousands of components, but usually only a handful of distinct ones. So the distinct components are stored once, as JSON, and each grid cell just refers to one of them by index:
+//!
+//! - the magic bytes `HANB`
+//! - the format version, shared with the JSON format, as a little-endian `u64`
+//! - every other project field except `components`, as a length-prefixed JSON object
+//! - the number of distinct components as a `u32`, followed by each of them as length-prefixed JSON
+//! - the number of cells as a `u32`, followed by each cell's x and y as `i16`s and its component index as an unsigned LEB128 number
+//!
+//! All lengths are little-endian `u32`s.
+
+use std::collections::HashMap;
+
+use serde::Deserialize as _;
+use serde_json::{json, Map, Value};
+
+use crate::project::{migrate, validate, Component, Error, Position, Project};
+
+const MAGIC: &[u8; 4] = b"HANB";
+
+pub fn encode(project: &Project) -> Result<Vec<u8>, Error> {
+	let Value::Object(mut metadata) = serde_json::to_value(project).map_err(Error::Serialize)? else {
+		unreachable!("projects serialize as objects");
+	};
+	let version = metadata
+		.remove("version")
+		.and_then(|version| version.as_u64());
+	metadata.remove("components");
+
+	let mut out = MAGIC.to_vec();
+	out.extend(version.unwrap_or(migrate::CURRENT_VERSION).to_le_bytes());
+	write_json(&mut out, &metadata)?;
+
+	let mut palette: Vec<Vec<u8>> = Vec::new();
+	let mut palette_indices: HashMap<Vec<u8>, u32> = HashMap::new();
+	let mut cells = Vec::with_capacity(project.components.len() * 6);
+	for (position, component) in &project.components {
+		let encoded = serde_json::to_vec(component).map_err(Error::Serialize)?;
+		let index = *palette_indices
+			.entry(encoded)
+			.or_insert_with_key(|encoded| {
+				palette.push(encoded.clone());
+				az::cast(palette.len() - 1)
+			});
+		cells.extend(position.x.to_le_bytes());
+		cells.extend(position.y.to_le_bytes());
+		write_leb128(&mut cells, index);
+	}
+
+	write_u32(&mut out, palette.len());
+	for component in palette {
+		write_u32(&mut out, component.len());
+		out.extend(component);
+	}
+	write_u32(&mut out, project.components.len());
+	out.extend(cells);
+
+	Ok(out)
+}
+
+/// Decodes a project, going through a JSON document only if it needs to be migrated or turns out to be invalid.
+pub fn decode(bytes: &[u8]) -> Result<Project, Error> {
+	let mut reader = Reader { bytes, offset: 0 };
+	if reader.take(MAGIC.len())? != MAGIC {
+		return Err(malformed("this is not a binary Hanlon project"));
+	}
+	let version = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+	let mut metadata: Map<String, Value> = reader.json()?;
+	metadata.insert("version".to_owned(), version.into());
+
+	let palette_len = reader.u32()?;
+	let palette: Vec<Value> = (0..palette_len)
+		.map(|_| reader.json())
+		.collect::<Result<_, _>>()?;
+	let cells_len = reader.u32()?;
+	let cells: Vec<(Position, usize)> = (0..cells_len)
+		.map(|_| {
+			let x = i16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+			let y = i16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+			let index = reader.leb128()?;
+			if index >= palette.len() {
+				return Err(malformed(&format!(
+					"component index {index} is out of range"
+				)));
+			}
+			Ok((Position { x, y }, index))
+		})
+		.collect::<Result<_, _>>()?;
+
+	if version == migrate::CURRENT_VERSION {
+		if let Ok(project) = decode_directly(&metadata, &palette, &cells) {
+			return Ok(project);
+		}
+	}
+
+	// either the document needs migrating or something is wrong with it, and the slow path will report what.
+	let components = cells
+		.into_iter()
+		.map(|(position, index)| json!([position, palette[index]]))
+		.collect();
+	metadata.insert("components".to_owned(), Value::Array(components));
+	Project::from_document(Value::Object(metadata), None)
+}
+
+fn decode_directly(
+	metadata: &Map<String, Value>,
+	palette: &[Value],
+	cells: &[(Position, usize)],
+) -> Result<Project, Error> {
+	// each distinct component only needs checking once. If any is invalid, the slow path reports where.
+	let components = palette
+		.iter()
+		.map(|component| json!([Position { x: 0, y: 0 }, component]))
+		.collect();
+	validate::check(&json!({ "components": Value::Array(components) }), None)?;
+
+	let palette: Vec<Component> = palette
+		.iter()
+		.map(Component::deserialize)
+		.collect::<Result<_, _>>()
+		.map_err(|err| Error::from_json(&err, None))?;
+
+	let mut document = metadata.clone();
+	document.insert("components".to_owned(), Value::Array(Vec::new()));
+	let mut project = Project::from_document(Value::Object(document), None)?;
+	project.components = cells
+		.iter()
+		.map(|&(position, index)| (position, palette[index]))
+		.collect();
+	Ok(project)
+}
+
+fn malformed(message: &str) -> Error {
+	Error::Parse {
+		message: message.to_owned(),
+		location: None,
+	}
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+	out.extend(
+		u32::try_from(value)
+			.expect("too many items for the binary format")
+			.to_le_bytes(),
+	);
+}
+
+fn write_json(out: &mut Vec<u8>, value: &impl serde::Serialize) -> Result<(), Error> {
+	let json = serde_json::to_vec(value).map_err(Error::Serialize)?;
+	write_u32(out, json.len());
+	out.extend(json);
+	Ok(())
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: u32) {
+	loop {
+		let byte = az::cast::<_, u8>(value & 0x7f);
+		value >>= 7;
+		if value == 0 {
+			out.push(byte);
+			break;
+		}
+		out.push(byte | 0x80);
+	}
+}
+
+struct Reader<'a> {
+	bytes: &'a [u8],
+	offset: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+		let taken = self
+			.bytes
+			.get(self.offset..self.offset + len)
+			.ok_or_else(|| malformed("the file ends unexpectedly"))?;
+		self.offset += len;
+		Ok(taken)
+	}
+
+	fn u32(&mut self) -> Result<usize, Error> {
+		Ok(az::cast(u32::from_le_bytes(
+			self.take(4)?.try_into().unwrap(),
+		)))
+	}
+
+	fn json<T: serde::de::DeserializeOwned>(&mut self) -> Result<T, Error> {
+		let len = self.u32()?;
+		serde_json::from_slice(self.take(len)?).map_err(|err| Error::from_json(&err, None))
+	}
+
+	fn leb128(&mut self) -> Result<usize, Error> {
+		let mut value = 0usize;
+		for shift in (0..32).step_by(7) {
+			let byte = self.take(1)?[0];
+			value |= usize::from(byte & 0x7f) << shift;
+			if byte & 0x80 == 0 {
+				return Ok(value);
+			}
+		}
+		Err(malformed("invalid component index"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::project::{Direction, Pellet};
+	use crate::sound::{Pitch, Velocity};
+
+	/// A project with every kind of component, a pellet, and non-default metadata and mix.
+	fn project() -> Project {
+		let mut project = Project::from_document(
+			json!({
+				"version": migrate::CURRENT_VERSION,
+				"components": [],
+				"tempo": 90,
+				"pellets": [],
+				"seed": 3,
+			}),
+			None,
+		)
+		.unwrap();
+		for (x, &component) in (0..).zip(Component::PALETTE_LIST.iter().chain([&Component::Debug])) {
+			project.components.insert(Position { x, y: -x }, component);
+		}
+		// the same component again, so that the palette is shared
+		project
+			.components
+			.insert(Position { x: 0, y: 1 }, Component::Guitar);
+		project.pellets.push(
+			Pellet::new_at(Position { x: 2, y: 3 }, Direction::Left)
+				.with_pitch(Pitch::new(5))
+				.with_velocity(Velocity::DEFAULT.raised_by(10)),
+		);
+		project.metadata.title = "Binary".to_owned();
+		project.metadata.created = Some(1_700_000_000);
+		project.mix.gains.insert("guitar".to_owned(), 0.5);
+		project.mix.limiter = false;
+		project
+	}
+
+	fn json_round_trip(project: &Project) -> Value {
+		let json = serde_json::to_string(project).unwrap();
+		serde_json::to_value(
+			Project::from_document(serde_json::from_str(&json).unwrap(), Some(&json)).unwrap(),
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn round_trips_like_json() {
+		let project = project();
+		let decoded = decode(&encode(&project).unwrap()).unwrap();
+		assert_eq!(
+			serde_json::to_value(&decoded).unwrap(),
+			json_round_trip(&project)
+		);
+		assert_eq!(decoded.components, project.components);
+	}
+
+	#[test]
+	fn rejects_cut_off_files() {
+		let encoded = encode(&project()).unwrap();
+		for len in [0, 3, 10, 20, encoded.len() / 2, encoded.len() - 1] {
+			assert!(decode(&encoded[..len]).is_err(), "cut off at {len}");
+		}
+	}
+
+	#[test]
+	fn rejects_component_indices_out_of_range() {
+		let mut project = project();
+		project.components.clear();
+		project
+			.components
+			.insert(Position { x: 0, y: 0 }, Component::Guitar);
+		let mut encoded = encode(&project).unwrap();
+		// the last byte is the only cell's index into the one-component palette
+		*encoded.last_mut().unwrap() = 1;
+		let error = decode(&encoded).unwrap_err();
+		assert!(
+			error
+				.to_string()
+				.contains("component index 1 is out of range"),
+			"{error}"
+		);
+	}
+
+	#[test]
+	fn rejects_invalid_components() {
+		let mut project = project();
+		project.components.clear();
+		project.components.insert(
+			Position { x: 4, y: 2 },
+			Component::Chance { probability: 0.5 },
+		);
+		let mut encoded = encode(&project).unwrap();
+		let probability = br#""probability":0.5"#;
+		let at = encoded
+			.windows(probability.len())
+			.position(|bytes| bytes == probability)
+			.unwrap();
+		// replaces the 0 of 0.5
+		encoded[at + probability.len() - 3] = b'2';
+		let Err(Error::InvalidValues(invalid)) = decode(&encoded) else {
+			panic!("a probability of 2.5 should be rejected");
+		};
+		assert_eq!(invalid[0].owner, "chance at (4, 2)");
+	}
+}
@@ -0,0 +1,36 @@
+use std::ffi::OsStr;
+use std::path::Path;
+
+pub mod binary;
+pub mod text;
+
+/// The ways a project can be stored on disk, chosen by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	/// `.han`, and anything we don't recognize.
+	Json,
+	/// `.hant`, a grid of glyphs that is easy to read and diff. See [`text`].
+	Text,
+	/// `.hanb`, a compact encoding for very large projects. See [`binary`].
+	Binary,
+}
+
+impl Format {
+	pub const ALL: [Self; 3] = [Self::Json, Self::Text, Self::Binary];
+
+	pub fn of(path: &Path) -> Self {
+		let extension = path.extension().and_then(OsStr::to_str);
+		Self::ALL
+			.into_iter()
+			.find(|format| Some(format.extension()) == extension)
+			.unwrap_or(Self::Json)
+	}
+
+	pub fn extension(self) -> &'static str {
+		match self {
+			Self::Json => "han",
+			Self::Text => "hant",
+			Self::Binary => "hanb",
+		}
+	}
+}
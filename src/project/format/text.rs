@@ -0,0 +1,581 @@
+//! A plain-text project format where the components are drawn as a grid of glyphs.
+//!
+//! ```text
+//! hanlon 1
+//! tempo 120
+//! grid -4 -4
+//! G  .  I  .  A  .  G
+//! .  .  .  .  .  .  .
+//! .  .  .  .  E^ .  .
+//! end
+//! at 0 -2 {"_type":"alternator","current_direction":"left"}
+//! ```
+//!
+//! - `hanlon <version>` must come first and gives the format version, shared with the JSON format.
+//! - `grid <x> <y>` starts a grid whose top-left cell is at `(x, y)`. Each following line up to `end` is a row of cells, each of which is a two-character glyph followed by a space. `.` is an empty cell.
+//! - `at <x> <y> <json>` places a component given as JSON, replacing the one from the grid. Components whose state can't be expressed by their glyph alone are written this way too.
+//! - `pellet <json>` adds a pellet.
+//! - `<key> <json>` sets any other project field, such as `tempo`.
+//! - Empty lines and lines starting with `#` are ignored outside of grids.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use serde_json::{json, Map, Value};
+
+use crate::project::component::{GateKind, Window};
+use crate::project::direction::Diagonal;
+use crate::project::error::Location;
+use crate::project::{BeatsPerMinute, Component, Direction, Error, Position, Project};
+use crate::sound::Pitch;
+
+const EMPTY: [char; 2] = ['.', ' '];
+
+const OUT_OF_BOUNDS: &str = "the grid extends past the edge of the canvas";
+
+/// Grids with more cells than this are written as a list of `at` lines instead, since sparse projects would produce enormous grids.
+const MAX_GRID_CELLS: usize = 1 << 16;
+
+/// Parses `text` into a JSON document with the same structure as the JSON format.
+pub fn parse(text: &str) -> Result<Value, Error> {
+	let mut document = Map::new();
+	let mut components = BTreeMap::new();
+	let mut pellets = Vec::new();
+
+	let mut lines = text.lines().zip(1..);
+	let error = |line: usize, column: usize, message: String| Error::Parse {
+		message,
+		location: Location::in_text(text, line, column),
+	};
+
+	let (version, header_line) = lines
+		.by_ref()
+		.find(|(line, _)| !is_skipped(line))
+		.map_or((None, 1), |(line, number)| {
+			(line.strip_prefix("hanlon ").map(str::trim), number)
+		});
+	let version: u64 = version
+		.and_then(|version| version.parse().ok())
+		.ok_or_else(|| error(header_line, 1, "expected `hanlon <version>`".to_owned()))?;
+	document.insert("version".to_owned(), version.into());
+
+	while let Some((line, number)) = lines.next() {
+		if is_skipped(line) {
+			continue;
+		}
+
+		let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+		let rest_column = keyword.len() + 2;
+		match keyword {
+			"grid" => {
+				let (x, y) = parse_position(rest)
+					.ok_or_else(|| error(number, rest_column, "expected `grid <x> <y>`".to_owned()))?;
+				let mut ended = false;
+				for (row, (line, number)) in lines.by_ref().enumerate() {
+					if line.trim_end() == "end" {
+						ended = true;
+						break;
+					}
+					let y = offset(y, row).ok_or_else(|| error(number, 1, OUT_OF_BOUNDS.to_owned()))?;
+					parse_row(text, line, number, (x, y), &mut components)?;
+				}
+				if !ended {
+					return Err(error(number, 1, "this grid has no `end`".to_owned()));
+				}
+			}
+			"at" => {
+				let mut parts = rest.splitn(3, ' ');
+				let position = parts
+					.next()
+					.zip(parts.next())
+					.and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)));
+				let (Some((x, y)), Some(json)) = (position, parts.next()) else {
+					return Err(error(
+						number,
+						rest_column,
+						"expected `at <x> <y> <json>`".to_owned(),
+					));
+				};
+				let json_column = line.len() - json.len() + 1;
+				components.insert((y, x), parse_json(text, number, json_column, json)?);
+			}
+			"pellet" => pellets.push(parse_json(text, number, rest_column, rest)?),
+			"components" | "pellets" | "version" => {
+				return Err(error(
+					number,
+					1,
+					format!("`{keyword}` cannot be set directly"),
+				));
+			}
+			key => {
+				document.insert(key.to_owned(), parse_json(text, number, rest_column, rest)?);
+			}
+		}
+	}
+
+	let components = components
+		.into_iter()
+		.map(|((y, x), component): ((i16, i16), Value)| json!([{ "x": x, "y": y }, component]))
+		.collect();
+	document.insert("components".to_owned(), Value::Array(components));
+	document.insert("pellets".to_owned(), Value::Array(pellets));
+
+	Ok(Value::Object(document))
+}
+
+pub fn render(project: &Project) -> Result<String, Error> {
+	let Value::Object(mut document) = serde_json::to_value(project).map_err(Error::Serialize)? else {
+		unreachable!("projects serialize as objects");
+	};
+	let version = document.remove("version").unwrap_or_default();
+	document.remove("components");
+	let pellets = document.remove("pellets");
+
+	let mut out = format!("hanlon {version}\n");
+	for (key, value) in &document {
+		writeln!(out, "{key} {value}").unwrap();
+	}
+
+	let mut params = Vec::new();
+	let positions = project.components.keys();
+	let bounds = positions
+		.clone()
+		.map(|position| position.x)
+		.min()
+		.zip(positions.clone().map(|position| position.x).max())
+		.zip(positions.clone().map(|position| position.y).min())
+		.zip(positions.map(|position| position.y).max());
+	let cells = bounds.map_or(0, |(((min_x, max_x), min_y), max_y)| {
+		(usize::from(max_x.abs_diff(min_x)) + 1) * (usize::from(max_y.abs_diff(min_y)) + 1)
+	});
+
+	match bounds {
+		Some((((min_x, max_x), min_y), max_y)) if cells <= MAX_GRID_CELLS => {
+			writeln!(out, "grid {min_x} {min_y}").unwrap();
+			for y in min_y..=max_y {
+				let mut row = String::new();
+				for x in min_x..=max_x {
+					let glyph = match project.components.get(&Position { x, y }) {
+						Some(&component) => {
+							let glyph = glyph(component);
+							if !round_trips(component, glyph)? {
+								params.push((y, x, component));
+							}
+							glyph
+						}
+						None => EMPTY,
+					};
+					row.extend(glyph);
+					row.push(' ');
+				}
+				out.push_str(row.trim_end());
+				out.push('\n');
+			}
+			out.push_str("end\n");
+		}
+		_ => params.extend(
+			project
+				.components
+				.iter()
+				.map(|(position, &component)| (position.y, position.x, component)),
+		),
+	}
+
+	params.sort_by_key(|&(y, x, _)| (y, x));
+	for (y, x, component) in params {
+		let component = serde_json::to_value(component).map_err(Error::Serialize)?;
+		writeln!(out, "at {x} {y} {component}").unwrap();
+	}
+
+	for pellet in pellets
+		.as_ref()
+		.and_then(Value::as_array)
+		.into_iter()
+		.flatten()
+	{
+		writeln!(out, "pellet {pellet}").unwrap();
+	}
+
+	Ok(out)
+}
+
+fn parse_row(
+	text: &str,
+	line: &str,
+	number: usize,
+	(x, y): (i16, i16),
+	components: &mut BTreeMap<(i16, i16), Value>,
+) -> Result<(), Error> {
+	let chars: Vec<char> = line.chars().collect();
+	for (column, cell) in chars.chunks(3).enumerate() {
+		let glyph = [cell[0], cell.get(1).copied().unwrap_or(' ')];
+		if glyph == EMPTY || glyph == [' ', ' '] {
+			continue;
+		}
+
+		let error = |message: String| Error::Parse {
+			message,
+			location: Location::in_text(text, number, column * 3 + 1),
+		};
+		let component = from_glyph(glyph)
+			.ok_or_else(|| error(format!("unknown glyph `{}`", String::from_iter(glyph))))?;
+		let x = offset(x, column).ok_or_else(|| error(OUT_OF_BOUNDS.to_owned()))?;
+		let component = serde_json::to_value(component).map_err(Error::Serialize)?;
+		components.insert((y, x), component);
+	}
+	Ok(())
+}
+
+fn is_skipped(line: &str) -> bool {
+	line.trim().is_empty() || line.starts_with('#')
+}
+
+fn parse_position(text: &str) -> Option<(i16, i16)> {
+	let (x, y) = text.trim().split_once(' ')?;
+	Some((x.parse().ok()?, y.trim().parse().ok()?))
+}
+
+fn offset(base: i16, by: usize) -> Option<i16> {
+	base.checked_add_unsigned(u16::try_from(by).ok()?)
+}
+
+fn parse_json(text: &str, line: usize, column: usize, json: &str) -> Result<Value, Error> {
+	serde_json::from_str(json).map_err(|err| match Error::from_json(&err, None) {
+		Error::Parse { message, .. } => Error::Parse {
+			message,
+			location: Location::in_text(text, line, column + err.column().saturating_sub(1)),
+		},
+		other => other,
+	})
+}
+
+/// Whether the glyph alone is enough to reconstruct the component.
+fn round_trips(component: Component, glyph: [char; 2]) -> Result<bool, Error> {
+	let Some(reconstructed) = from_glyph(glyph) else {
+		return Ok(false);
+	};
+	Ok(
+		serde_json::to_value(component).map_err(Error::Serialize)?
+			== serde_json::to_value(reconstructed).map_err(Error::Serialize)?,
+	)
+}
+
+fn arrow(direction: Direction) -> char {
+	match direction {
+		Direction::Up => '^',
+		Direction::Right => '>',
+		Direction::Down => 'v',
+		Direction::Left => '<',
+	}
+}
+
+fn from_arrow(arrow: char) -> Option<Direction> {
+	Some(match arrow {
+		'^' => Direction::Up,
+		'>' => Direction::Right,
+		'v' => Direction::Down,
+		'<' => Direction::Left,
+		_ => return None,
+	})
+}
+
+/// Numbers above 9 are written as `#`, and the exact value is given by an `at` line.
+fn digit(number: u8) -> char {
+	char::from_digit(number.into(), 10).unwrap_or('#')
+}
+
+fn from_digit(digit: char, default: u8) -> Option<u8> {
+	if digit == '#' {
+		Some(default)
+	} else {
+		digit.to_digit(10).map(az::cast)
+	}
+}
+
+fn glyph(component: Component) -> [char; 2] {
+	match component {
+		Component::Emitter { direction } => ['E', arrow(direction)],
+		Component::Consumer => ['X', ' '],
+		Component::RightTurn => ['R', ' '],
+		Component::LeftTurn => ['L', ' '],
+		Component::UTurn => ['U', ' '],
+		Component::Splitter { straight: false } => ['S', '2'],
+		Component::Splitter { straight: true } => ['S', '3'],
+		Component::Mirror {
+			diagonal: Diagonal::Rising,
+		} => ['/', ' '],
+		Component::Mirror {
+			diagonal: Diagonal::Falling,
+		} => ['\\', ' '],
+		Component::OneWay { direction } => ['O', arrow(direction)],
+		Component::Portal { channel } => ['P', digit(channel)],
+		Component::Alternator { .. } => ['A', ' '],
+		Component::Debug => ['D', ' '],
+		Component::IncrementPitch { .. } => ['I', ' '],
+		Component::Guitar => ['G', ' '],
+		Component::Half { .. } => ['H', ' '],
+		Component::Counter { every, .. } => ['C', digit(every)],
+		Component::Switch { every, .. } => ['N', digit(every)],
+		Component::Chance { .. } => ['%', ' '],
+		Component::RandomDirection => ['?', ' '],
+		Component::RandomPitch { .. } => ['~', ' '],
+		Component::Gate {
+			kind: GateKind::And,
+			output,
+			..
+		} => ['&', arrow(output)],
+		Component::Gate {
+			kind: GateKind::Xor,
+			output,
+			..
+		} => ['*', arrow(output)],
+		Component::Latch { .. } => ['Q', ' '],
+		Component::SetTempo { .. } => ['T', '='],
+		Component::ScaleTempo { .. } => ['T', '*'],
+		Component::Instrument { instrument } => ['M', digit(instrument)],
+		Component::Accent { .. } => ['V', '+'],
+		Component::Attenuate { .. } => ['V', '-'],
+	}
+}
+
+/// The component with the default settings for `glyph`.
+fn from_glyph(glyph: [char; 2]) -> Option<Component> {
+	Some(match glyph {
+		['E', arrow] => Component::Emitter {
+			direction: from_arrow(arrow)?,
+		},
+		['X', ' '] => Component::Consumer,
+		['R', ' '] => Component::RightTurn,
+		['L', ' '] => Component::LeftTurn,
+		['U', ' '] => Component::UTurn,
+		['S', '2'] => Component::Splitter { straight: false },
+		['S', '3'] => Component::Splitter { straight: true },
+		['/', ' '] => Component::Mirror {
+			diagonal: Diagonal::Rising,
+		},
+		['\\', ' '] => Component::Mirror {
+			diagonal: Diagonal::Falling,
+		},
+		['O', arrow] => Component::OneWay {
+			direction: from_arrow(arrow)?,
+		},
+		['P', digit] => Component::Portal {
+			channel: from_digit(digit, 0)?,
+		},
+		['A', ' '] => Component::Alternator {
+			current_direction: Direction::Up,
+		},
+		['D', ' '] => Component::Debug,
+		['I', ' '] => Component::IncrementPitch {
+			current: Pitch::new(0),
+		},
+		['G', ' '] => Component::Guitar,
+		['H', ' '] => Component::Half { state: false },
+		['C', digit] => Component::Counter {
+			every: from_digit(digit, 4)?,
+			offset: 0,
+			count: 0,
+		},
+		['N', digit] => Component::Switch {
+			every: from_digit(digit, 4)?,
+			count: 0,
+		},
+		['%', ' '] => Component::Chance { probability: 0.5 },
+		['?', ' '] => Component::RandomDirection,
+		['~', ' '] => Component::RandomPitch {
+			low: Pitch::new(0),
+			high: Pitch::new(Pitch::MAX),
+		},
+		[kind @ ('&' | '*'), arrow] => Component::Gate {
+			kind: if kind == '&' {
+				GateKind::And
+			} else {
+				GateKind::Xor
+			},
+			window: Window::Tick,
+			output: from_arrow(arrow)?,
+			pending: None,
+		},
+		['Q', ' '] => Component::Latch {
+			set: Direction::Right,
+			reset: Direction::Left,
+			on: false,
+		},
+		['T', '='] => Component::SetTempo {
+			tempo: BeatsPerMinute(120.0),
+		},
+		['T', '*'] => Component::ScaleTempo { factor: 1.1 },
+		['M', digit] => Component::Instrument {
+			instrument: from_digit(digit, 0)?,
+		},
+		['V', '+'] => Component::Accent { amount: 16 },
+		['V', '-'] => Component::Attenuate { amount: 16 },
+		_ => return None,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::project::{migrate, Pellet};
+
+	fn project(components: &[(i16, i16, Component)]) -> Project {
+		let mut project = Project::from_document(
+			json!({
+				"version": migrate::CURRENT_VERSION,
+				"components": [],
+				"tempo": 120,
+				"pellets": [],
+			}),
+			None,
+		)
+		.unwrap();
+		for &(x, y, component) in components {
+			project.components.insert(Position { x, y }, component);
+		}
+		project
+	}
+
+	fn parse_project(text: &str) -> Project {
+		Project::from_document(parse(text).unwrap(), None).unwrap()
+	}
+
+	fn location(error: &Error) -> (usize, usize) {
+		let location = error.location().unwrap();
+		(location.line, location.column)
+	}
+
+	#[test]
+	fn parses_grids_and_parameters() {
+		let project = parse_project(
+			"# a comment\n\
+			hanlon 1\n\
+			tempo 90\n\
+			\n\
+			grid -1 5\n\
+			E> G  .\n\
+			.  %  C3\n\
+			end\n\
+			at 0 6 {\"_type\":\"chance\",\"probability\":0.25}\n\
+			pellet {\"origin\":{\"x\":3,\"y\":4},\"direction\":\"down\",\"offset_along_direction\":0,\"pitch\":2}\n",
+		);
+		assert_eq!(project.tempo, BeatsPerMinute(90.0));
+		let components: Vec<_> = project.components.into_iter().collect();
+		assert_eq!(
+			components,
+			[
+				(
+					Position { x: -1, y: 5 },
+					Component::Emitter {
+						direction: Direction::Right
+					}
+				),
+				(Position { x: 0, y: 5 }, Component::Guitar),
+				(
+					Position { x: 0, y: 6 },
+					Component::Chance { probability: 0.25 }
+				),
+				(
+					Position { x: 1, y: 6 },
+					Component::Counter {
+						every: 3,
+						offset: 0,
+						count: 0
+					}
+				),
+			]
+		);
+		assert_eq!(project.pellets.len(), 1);
+		assert_eq!(project.pellets[0].pitch, Pitch::new(2));
+	}
+
+	#[test]
+	fn parses_ragged_rows() {
+		let project = parse_project("hanlon 1\ntempo 120\ngrid 0 0\nG\n.  .  G\n.  G \nend\n");
+		let positions: Vec<_> = project.components.into_keys().collect();
+		assert_eq!(
+			positions,
+			[
+				Position { x: 0, y: 0 },
+				Position { x: 1, y: 2 },
+				Position { x: 2, y: 1 },
+			]
+		);
+	}
+
+	#[test]
+	fn rejects_bad_glyphs() {
+		let error = parse("hanlon 1\ngrid 0 0\nG  .  Z?\nend\n").unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"invalid project data: unknown glyph `Z?`"
+		);
+		assert_eq!(location(&error), (3, 7));
+
+		let error = parse("hanlon 1\ngrid 0 0\nE! \nend\n").unwrap_err();
+		assert_eq!(location(&error), (3, 1));
+	}
+
+	#[test]
+	fn rejects_bad_lines() {
+		let error = parse("tempo 120\n").unwrap_err();
+		assert_eq!(location(&error), (1, 1));
+		let error = parse("hanlon 1\ngrid 0 0\nG\n").unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"invalid project data: this grid has no `end`"
+		);
+		let error = parse("hanlon 1\nat 1 {}\n").unwrap_err();
+		assert_eq!(location(&error), (2, 4));
+		let error = parse("hanlon 1\ntempo [1,\n").unwrap_err();
+		assert_eq!(location(&error).0, 2);
+	}
+
+	#[test]
+	fn writes_a_grid_and_parameters() {
+		let mut project = project(&[
+			(
+				0,
+				0,
+				Component::Emitter {
+					direction: Direction::Up,
+				},
+			),
+			(2, 0, Component::Guitar),
+			(0, -2, Component::Chance { probability: 0.25 }),
+		]);
+		project
+			.pellets
+			.push(Pellet::new_at(Position { x: 1, y: 1 }, Direction::Right));
+		let text = render(&project).unwrap();
+		assert!(text.starts_with("hanlon 1\n"), "{text}");
+		assert!(text.contains("\ntempo 120.0\n"), "{text}");
+		assert!(
+			text.contains("\ngrid 0 -2\n%  .  .\n.  .  .\nE^ .  G\nend\nat 0 -2 {\"_type\":\"chance\",\"probability\":0.25}\npellet {"),
+			"{text}"
+		);
+	}
+
+	#[test]
+	fn round_trips_every_component() {
+		let components: Vec<_> = (0..)
+			.zip(Component::PALETTE_LIST.iter().chain([
+				&Component::Debug,
+				&Component::Counter {
+					every: 12,
+					offset: 5,
+					count: 0,
+				},
+				&Component::Chance { probability: 0.75 },
+			]))
+			.map(|(x, &component)| (x % 7, x / 7, component))
+			.collect();
+		let project = project(&components);
+		let parsed = parse_project(&render(&project).unwrap());
+		assert_eq!(parsed.components, project.components);
+		assert_eq!(
+			serde_json::to_value(&parsed).unwrap(),
+			serde_json::to_value(&project).unwrap()
+		);
+	}
+}
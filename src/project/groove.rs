@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+/// Delays some of the beats emitters fire on, giving playback a swing instead of a strict grid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Groove {
+	pub template: Template,
+	/// How strongly `template` is applied, from 0 for a strict grid to 1.
+	pub amount: f32,
+}
+
+impl Groove {
+	/// How long after beat number `beat` its emitters fire, as a fraction of a beat.
+	pub fn delay(self, beat: u64) -> f32 {
+		let delays = self.template.delays();
+		// the first emitted beat is beat 1
+		let index = usize::try_from(beat.saturating_sub(1) % delays.len() as u64).unwrap();
+		delays[index] * self.amount.clamp(0.0, 1.0)
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Template {
+	/// Every other beat is played a triplet late.
+	#[default]
+	Swing,
+	/// Every other beat is played as if the one before it were dotted.
+	Dotted,
+	/// A looser feel where every beat but the first of four drags a little.
+	LaidBack,
+}
+
+impl Template {
+	pub const ALL: [Self; 3] = [Self::Swing, Self::Dotted, Self::LaidBack];
+
+	/// The delay of each beat at full strength, repeating.
+	fn delays(self) -> &'static [f32] {
+		match self {
+			Self::Swing => &[0.0, 1.0 / 3.0],
+			Self::Dotted => &[0.0, 0.5],
+			Self::LaidBack => &[0.0, 0.15, 0.05, 0.2],
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Swing => "Swing",
+			Self::Dotted => "Dotted",
+			Self::LaidBack => "Laid back",
+		}
+	}
+}
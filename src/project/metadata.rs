@@ -0,0 +1,75 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Information about a project that doesn't affect playback.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Metadata {
+	pub title: String,
+	pub author: String,
+	pub notes: String,
+	pub time_signature: TimeSignature,
+	pub key: Key,
+	/// When the project was first saved, in seconds since the Unix epoch.
+	pub created: Option<u64>,
+	/// When the project was last saved, in seconds since the Unix epoch.
+	pub modified: Option<u64>,
+}
+
+impl Metadata {
+	/// Records that the project is being saved now.
+	pub fn touch(&mut self) {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_or(0, |since| since.as_secs());
+		self.created.get_or_insert(now);
+		self.modified = Some(now);
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSignature {
+	/// How many beats there are in a bar.
+	pub beats: u8,
+	/// Which note value gets one beat, e.g. 4 for quarter notes.
+	pub note_value: u8,
+}
+
+impl TimeSignature {
+	pub const NOTE_VALUES: [u8; 6] = [1, 2, 4, 8, 16, 32];
+}
+
+impl Default for TimeSignature {
+	fn default() -> Self {
+		Self {
+			beats: 4,
+			note_value: 4,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Key {
+	/// Semitones above C.
+	pub root: u8,
+	pub mode: Mode,
+}
+
+impl Key {
+	pub const ROOT_NAMES: [&'static str; 12] = [
+		"C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+	];
+
+	pub fn root_name(self) -> &'static str {
+		Self::ROOT_NAMES[usize::from(self.root) % Self::ROOT_NAMES.len()]
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+	#[default]
+	Major,
+	Minor,
+}
@@ -0,0 +1,113 @@
+//! Upgrades project documents saved by older versions of Hanlon to the current format.
+//!
+//! Migrations operate on the raw JSON document rather than on `Project` so that they can deal with data that no longer deserializes.
+
+use serde_json::{Map, Value};
+
+use super::Error;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`.
+const MIGRATIONS: &[Migration] = &[v0_to_v1];
+
+/// The version of the file format written by this version of Hanlon.
+#[allow(clippy::cast_possible_truncation)] // at compile-time
+pub const CURRENT_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// The format version of `document`, which must be one that we can read.
+pub fn version_of(document: &Value) -> Result<u64, Error> {
+	let version = match document.get("version") {
+		// files from before the format was versioned have no version field
+		None => 0,
+		Some(version) => version
+			.as_u64()
+			.ok_or_else(|| Error::InvalidVersion(version.clone()))?,
+	};
+
+	if version > CURRENT_VERSION {
+		return Err(Error::NewerVersion {
+			version,
+			supported: CURRENT_VERSION,
+		});
+	}
+
+	Ok(version)
+}
+
+/// Upgrades `document` in place to `CURRENT_VERSION`.
+pub fn upgrade(document: &mut Value) -> Result<(), Error> {
+	let version = version_of(document)?;
+	let document = document.as_object_mut().ok_or_else(|| Error::Parse {
+		message: "the project must be a JSON object".to_owned(),
+		location: None,
+	})?;
+
+	for (from_version, migration) in (0..).zip(MIGRATIONS).skip(az::cast(version)) {
+		migration(document).map_err(|message| Error::Migration {
+			from_version,
+			message,
+		})?;
+	}
+
+	document.insert("version".to_owned(), CURRENT_VERSION.into());
+	Ok(())
+}
+
+/// Version 1 only introduced the version field itself.
+#[allow(clippy::unnecessary_wraps)] // must match `Migration`
+fn v0_to_v1(_document: &mut Map<String, Value>) -> Result<(), String> {
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+	use crate::project::Project;
+
+	#[test]
+	fn upgrades_documents_without_a_version() {
+		let mut document = json!({
+			"components": [[{ "x": 0, "y": 0 }, { "_type": "emitter", "direction": "right" }]],
+			"tempo": 240,
+			"pellets": [],
+		});
+		assert_eq!(version_of(&document).unwrap(), 0);
+		upgrade(&mut document).unwrap();
+		assert_eq!(document["version"], CURRENT_VERSION);
+
+		let project = Project::from_document(document, None).unwrap();
+		assert_eq!(project.components.len(), 1);
+	}
+
+	#[test]
+	fn rejects_newer_versions() {
+		let mut document = json!({ "version": CURRENT_VERSION + 1 });
+		assert!(matches!(
+			upgrade(&mut document),
+			Err(Error::NewerVersion { version, supported: CURRENT_VERSION }) if version == CURRENT_VERSION + 1
+		));
+	}
+
+	#[test]
+	fn rejects_versions_that_are_not_whole_numbers() {
+		for version in [json!(1.5), json!(-1), json!("1")] {
+			let mut document = json!({ "version": version });
+			assert!(
+				matches!(upgrade(&mut document), Err(Error::InvalidVersion(invalid)) if invalid == version),
+				"{version}"
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_documents_that_are_not_objects() {
+		let mut document = json!([1, 2, 3]);
+		assert!(matches!(
+			upgrade(&mut document),
+			Err(Error::Parse { location: None, .. })
+		));
+	}
+}
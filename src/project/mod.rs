@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::ops::RangeInclusive;
 use std::path::Path;
 use std::time::Duration;
 
@@ -8,21 +9,44 @@ use serde::{Deserialize, Serialize};
 mod colors;
 pub mod component;
 pub mod direction;
+pub mod error;
+pub mod format;
+pub mod groove;
+pub mod metadata;
+mod migrate;
 pub mod pellet;
 pub mod position;
+pub mod random;
+pub mod validate;
 
 pub use self::component::Component;
-use self::component::ShouldEmit;
+use self::component::{Context, ShouldEmit, Time};
 pub use self::direction::Direction;
+pub use self::error::Error;
+use self::format::Format;
+pub use self::groove::Groove;
+pub use self::metadata::Metadata;
 pub use self::pellet::Pellet;
 pub use self::position::Position;
-use crate::sound::Sound;
+use self::random::Rng;
+use crate::sound::bank::{self, Sample};
+use crate::sound::instrument::Instrument;
+use crate::sound::mixer::{Mix, Panning};
+use crate::sound::{Note, Sound, Type as SoundType};
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[repr(transparent)]
 pub struct BeatsPerMinute(pub f32);
 
 impl BeatsPerMinute {
+	pub const MIN: Self = Self(1.0);
+	pub const MAX: Self = Self(1000.0);
+
+	#[must_use]
+	pub fn clamp(self) -> Self {
+		Self(self.0.clamp(Self::MIN.0, Self::MAX.0))
+	}
+
 	pub fn beat_time(self) -> Duration {
 		Duration::from_secs_f32(60.0 / self.0)
 	}
@@ -32,53 +56,227 @@ impl BeatsPerMinute {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Project {
+	/// The version of the file format. Always `migrate::CURRENT_VERSION` once loaded.
+	version: u64,
+	#[serde(default)]
+	pub metadata: Metadata,
 	#[serde_as(as = "Vec<(_, _)>")]
-	pub components: HashMap<Position, Component>,
+	/// Sorted, so that components always run in the same order and random ones play out the same way.
+	pub components: BTreeMap<Position, Component>,
 	pub tempo: BeatsPerMinute,
+	#[serde(default)]
+	pub groove: Groove,
+	#[serde(default)]
+	pub mix: Mix,
+	/// Instruments made from sample files, played by `Instrument` components.
+	#[serde(default)]
+	pub instruments: Vec<Instrument>,
+	/// The decoded samples of each of `instruments`, one for every pitch.
+	#[serde(skip)]
+	instrument_samples: Vec<Vec<Sample>>,
 	pub pellets: Vec<Pellet>,
+	/// Seeds the random components so that playback is reproducible.
+	#[serde(default)]
+	pub seed: u64,
+	#[serde(skip)]
+	rng: Rng,
 	#[serde(skip)]
 	steps: u32,
+	/// Whether the emitters have yet to fire for the current beat, since the groove may delay them.
+	#[serde(skip)]
+	emit_pending: bool,
+	#[serde(skip)]
+	time: Time,
+	/// The tempo set by tempo components, which replaces `tempo` until playback is stopped.
+	#[serde(skip)]
+	playing_tempo: Option<BeatsPerMinute>,
+	#[serde(skip)]
+	pub paused: bool,
+	/// The columns the editor is showing, for panning notes across it.
+	#[serde(skip)]
+	pub viewport: Option<RangeInclusive<f32>>,
 }
 
 impl Project {
-	pub fn read(file: &Path) -> Result<Self, String> {
-		let file = File::open(file).map_err(|err| format!("could not open file at {file:?}: {err}"))?;
-		serde_json::from_reader(std::io::BufReader::new(file))
-			.map_err(|err| format!("invalid project data: {err}"))
-	}
-
-	pub fn write(&self, file: &Path) -> Result<(), String> {
-		let file =
-			File::create(file).map_err(|err| format!("could not open/create file at {file:?}: {err}"))?;
-		serde_json::to_writer(file, self).map_err(|err| {
-			if err.is_io() {
-				format!("failed to write to file: {err:?}")
-			} else {
-				unreachable!("serde_json error other than IO: {err:?}");
+	pub fn read(path: &Path) -> Result<Self, Error> {
+		let read_text = || std::fs::read_to_string(path).map_err(Error::io(path));
+		let mut project = match Format::of(path) {
+			Format::Json => {
+				let text = read_text()?;
+				let document =
+					serde_json::from_str(&text).map_err(|err| Error::from_json(&err, Some(&text)))?;
+				Self::from_document(document, Some(&text))
 			}
-		})
+			Format::Text => Self::from_document(format::text::parse(&read_text()?)?, None),
+			Format::Binary => format::binary::decode(&std::fs::read(path).map_err(Error::io(path))?),
+		}?;
+		project.load_instruments(path.parent().unwrap_or_else(|| Path::new("")))?;
+		Ok(project)
+	}
+
+	/// Decodes the samples of every instrument, whose paths are relative to `directory`.
+	pub fn load_instruments(&mut self, directory: &Path) -> Result<(), Error> {
+		self.instrument_samples = self
+			.instruments
+			.iter()
+			.map(|instrument| instrument.load(directory))
+			.collect::<Result<_, _>>()?;
+		Ok(())
+	}
+
+	/// The sample to play for `sound`, if its instrument exists.
+	pub fn sample(&self, sound: Sound) -> Option<&Sample> {
+		match sound.ty {
+			SoundType::Custom(index) => self
+				.instrument_samples
+				.get(usize::from(index))?
+				.get(usize::from(sound.pitch.semitones())),
+			SoundType::Guitar => bank::sample(sound),
+		}
+	}
+
+	/// `json` is the text of the document, if it came from a JSON file. Errors can then point at the offending line.
+	fn from_document(mut document: serde_json::Value, json: Option<&str>) -> Result<Self, Error> {
+		let up_to_date = migrate::version_of(&document)? == migrate::CURRENT_VERSION;
+		if !up_to_date {
+			migrate::upgrade(&mut document)?;
+		}
+		validate::check(&document, json.filter(|_| up_to_date))?;
+
+		let mut project: Self = match json {
+			Some(json) if up_to_date => {
+				serde_json::from_str(json).map_err(|err| Error::from_json(&err, Some(json)))?
+			}
+			_ => serde_json::from_value(document).map_err(|err| Error::from_json(&err, None))?,
+		};
+		project.rng = Rng::new(project.seed);
+		Ok(project)
+	}
+
+	pub fn write(&self, path: &Path) -> Result<(), Error> {
+		match Format::of(path) {
+			Format::Json => {
+				let file = File::create(path).map_err(Error::io(path))?;
+				serde_json::to_writer(file, self).map_err(|err| {
+					if err.is_io() {
+						Error::io(path)(err.into())
+					} else {
+						Error::Serialize(err)
+					}
+				})
+			}
+			Format::Text => std::fs::write(path, format::text::render(self)?).map_err(Error::io(path)),
+			Format::Binary => {
+				std::fs::write(path, format::binary::encode(self)?).map_err(Error::io(path))
+			}
+		}
 	}
 }
 
 impl Project {
+	/// Stops playback, removing all pellets and putting every component back into its initial state.
+	pub fn reset(&mut self) {
+		self.paused = true;
+		self.pellets.clear();
+		self.steps = 0;
+		self.emit_pending = false;
+		self.time = Time::default();
+		self.rng = Rng::new(self.seed);
+		self.playing_tempo = None;
+		for component in self.components.values_mut() {
+			component.reset();
+		}
+	}
+
+	/// Picks a new seed, changing the outcome of every random component.
+	pub fn reroll(&mut self) {
+		self.seed = self.rng.next_u64();
+		self.rng = Rng::new(self.seed);
+	}
+
+	/// The tempo playback is running at.
+	pub fn current_tempo(&self) -> BeatsPerMinute {
+		self.playing_tempo.unwrap_or(self.tempo)
+	}
+
+	/// Changes the tempo of the project, taking effect immediately.
+	pub fn set_tempo(&mut self, tempo: BeatsPerMinute) {
+		self.tempo = tempo.clamp();
+		self.playing_tempo = None;
+	}
+
+	/// How many ticks playback has run for. There are 60 per second.
+	pub fn tick(&self) -> u64 {
+		self.time.tick
+	}
+
 	#[must_use]
-	pub fn step_pellets(&mut self) -> Vec<Sound> {
+	pub fn step_pellets(&mut self) -> Vec<Note> {
 		self.steps = self.steps.wrapping_add(1);
+		self.time.tick += 1;
+		let tempo = self.current_tempo();
 
 		self.pellets.retain_mut(|pellet| {
-			pellet.advance_by(self.tempo.0 / 1000.0);
+			pellet.advance_by(tempo.0 / 1000.0);
 			!pellet.should_remove()
 		});
 
 		// steps run at 60 fps
-		let seconds = az::cast::<_, f32>(self.steps) / 60.0;
-		let minutes = seconds / 60.0;
-		if minutes * self.tempo.0 > 1.0 {
-			self.run_emitters();
+		let beats_since = |steps: u32| az::cast::<_, f32>(steps) / 60.0 / 60.0 * tempo.0;
+		if beats_since(self.steps) > 1.0 {
 			self.steps = 0;
+			self.time.beat += 1;
+			self.emit_pending = true;
 		}
+		if self.emit_pending && beats_since(self.steps) >= self.groove.delay(self.time.beat) {
+			self.run_emitters();
+			self.emit_pending = false;
+		}
+
+		self.run_ticks();
+		let tick = self.time.tick;
+		self
+			.check_collisions()
+			.into_iter()
+			.map(|(sound, position)| Note {
+				sound,
+				tick,
+				pan: self.pan(sound.ty, position),
+			})
+			.collect()
+	}
 
-		self.check_collisions()
+	/// Where a note played by the component at `position` is placed between the speakers.
+	fn pan(&self, ty: SoundType, position: Position) -> f32 {
+		let instrument = match ty {
+			SoundType::Custom(index) => self
+				.instruments
+				.get(usize::from(index))
+				.map_or(0.0, |instrument| instrument.pan),
+			SoundType::Guitar => self.mix.pan(ty),
+		};
+		let span = match self.mix.panning {
+			Panning::Instrument => None,
+			Panning::Viewport => self.viewport.clone(),
+			Panning::Span { left, right } => Some(f32::from(left)..=f32::from(right)),
+		};
+		let by_position = span
+			.filter(|span| span.start() < span.end())
+			.map_or(0.0, |span| {
+				(f32::from(position.x) - span.start()) / (span.end() - span.start()) * 2.0 - 1.0
+			});
+		(instrument + by_position).clamp(-1.0, 1.0)
+	}
+
+	/// The positions of all portals on `channel`.
+	pub fn portals(&self, channel: u8) -> impl Iterator<Item = Position> + '_ {
+		self
+			.components
+			.iter()
+			.filter_map(move |(&pos, component)| match component {
+				Component::Portal { channel: other } if *other == channel => Some(pos),
+				_ => None,
+			})
 	}
 
 	fn run_emitters(&mut self) {
@@ -92,10 +290,30 @@ impl Project {
 		}
 	}
 
+	fn run_ticks(&mut self) {
+		for (&pos, component) in &mut self.components {
+			if let Some((direction, pitch, velocity)) = component.on_tick(self.time) {
+				self.pellets.push(
+					Pellet::new_at(pos, direction)
+						.with_pitch(pitch)
+						.with_velocity(velocity),
+				);
+			}
+		}
+	}
+
+	/// Returns the sounds played, and where the components that played them are.
 	#[must_use]
-	fn check_collisions(&mut self) -> Vec<Sound> {
+	fn check_collisions(&mut self) -> Vec<(Sound, Position)> {
 		let mut new_pellets = vec![];
 		let mut sounds = vec![];
+		let mut teleports = vec![];
+		let mut tempo = self.current_tempo();
+		let mut context = Context {
+			rng: &mut self.rng,
+			time: self.time,
+			tempo: &mut tempo,
+		};
 
 		self.pellets.retain_mut(|pellet| {
 			let pos = pellet.pos_rounded();
@@ -105,14 +323,23 @@ impl Project {
 					let ShouldEmit {
 						sound,
 						pitch,
+						velocity,
 						directions,
-					} = component.on_pellet(*pellet);
-					sounds.extend(sound);
+						teleport,
+					} = component.on_pellet(*pellet, &mut context);
+					sounds.extend(sound.map(|sound| (sound, pos)));
+					if let Some(channel) = teleport {
+						teleports.push((channel, pos, pellet.direction(), pitch, velocity));
+					}
+					let emit = |direction| {
+						Pellet::new_at(pos, direction)
+							.with_pitch(pitch)
+							.with_velocity(velocity)
+					};
 					let mut directions = directions.iter();
 					return if let Some(first) = directions.next() {
-						*pellet = Pellet::new_at(pos, first).with_pitch(pitch);
-						new_pellets
-							.extend(directions.map(|direction| Pellet::new_at(pos, direction).with_pitch(pitch)));
+						*pellet = emit(first);
+						new_pellets.extend(directions.map(emit));
 						true
 					} else {
 						false
@@ -123,8 +350,69 @@ impl Project {
 			true
 		});
 
+		for (channel, entrance, direction, pitch, velocity) in teleports {
+			new_pellets.extend(
+				self
+					.portals(channel)
+					.filter(|&exit| exit != entrance)
+					.map(|exit| {
+						Pellet::new_at(exit, direction)
+							.with_pitch(pitch)
+							.with_velocity(velocity)
+					}),
+			);
+		}
+
 		self.pellets.extend(new_pellets);
+		if tempo != self.current_tempo() {
+			self.playing_tempo = Some(tempo);
+		}
 
 		sounds
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Three emitters, each sending pellets through a random pitch and a random direction to one of three guitars.
+	fn random_project() -> Project {
+		let mut components = Vec::new();
+		for y in [0, 4, 8] {
+			let at = |x: i16, y: i16| serde_json::json!({ "x": x, "y": y });
+			components.extend([
+				serde_json::json!([at(0, y), { "_type": "emitter", "direction": "right" }]),
+				serde_json::json!([at(1, y), { "_type": "random_pitch", "low": 0, "high": 7 }]),
+				serde_json::json!([at(2, y), { "_type": "random_direction" }]),
+				serde_json::json!([at(2, y - 1), { "_type": "guitar" }]),
+				serde_json::json!([at(3, y), { "_type": "guitar" }]),
+				serde_json::json!([at(2, y + 1), { "_type": "guitar" }]),
+			]);
+		}
+		Project::from_document(
+			serde_json::json!({
+				"version": migrate::CURRENT_VERSION,
+				"components": components,
+				"tempo": 240,
+				"pellets": [],
+				"seed": 7,
+			}),
+			None,
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn random_components_play_the_same_notes_every_time() {
+		let play = || {
+			let mut project = random_project();
+			(0..600)
+				.flat_map(|_| project.step_pellets())
+				.collect::<Vec<_>>()
+		};
+		let notes = play();
+		assert!(notes.len() > 10);
+		assert_eq!(notes, play());
+	}
+}
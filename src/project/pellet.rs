@@ -2,7 +2,7 @@ use egui::Vec2;
 use serde::{Deserialize, Serialize};
 
 use super::{Direction, Position};
-use crate::sound::Pitch;
+use crate::sound::{Pitch, Velocity};
 
 // "along direction" = x if direction is horizontal, y otherwise
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -11,6 +11,8 @@ pub struct Pellet {
 	offset_along_direction: i32,
 	origin: Position,
 	pub pitch: Pitch,
+	#[serde(default)]
+	pub velocity: Velocity,
 }
 
 const OFFSET_FIXED_POINT_FACTOR: i32 = 10000;
@@ -31,12 +33,17 @@ impl Pellet {
 		Self { pitch, ..self }
 	}
 
+	pub fn with_velocity(self, velocity: Velocity) -> Self {
+		Self { velocity, ..self }
+	}
+
 	pub fn new_at(at: Position, direction: Direction) -> Self {
 		Self {
 			direction,
 			offset_along_direction: i32::from(at.position_along(direction)) * OFFSET_FIXED_POINT_FACTOR,
 			origin: at,
 			pitch: Pitch::new(0),
+			velocity: Velocity::DEFAULT,
 		}
 	}
 
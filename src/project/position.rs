@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 use super::Direction;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[serde(rename_all = "snake_case")]
 pub struct Position {
 	pub x: i16,
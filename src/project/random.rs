@@ -0,0 +1,30 @@
+/// A small seeded PRNG, specifically `SplitMix64`.
+///
+/// This is implemented here rather than pulled from a crate so that the sequence produced for a given seed can never change under us, which would change how saved projects sound.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rng(u64);
+
+impl Rng {
+	pub fn new(seed: u64) -> Self {
+		Self(seed)
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^ (z >> 31)
+	}
+
+	/// A uniformly distributed number in `0.0..1.0`.
+	pub fn next_f32(&mut self) -> f32 {
+		// the top 24 bits fit exactly in an f32's mantissa
+		az::cast::<_, f32>(self.next_u64() >> 40) / az::cast::<_, f32>(1u32 << 24)
+	}
+
+	/// A uniformly distributed number in `0..bound`.
+	pub fn below(&mut self, bound: u8) -> u8 {
+		az::cast(self.next_u64() % u64::from(bound))
+	}
+}
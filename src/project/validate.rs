@@ -0,0 +1,553 @@
+//! Checks the bounded values in a project document before it is deserialized.
+//!
+//! Deserialization stops at the first problem, but a project generated by a script may have many, so they are all collected here instead.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+use super::component::TEMPO_FACTORS;
+use super::error::Location;
+use super::metadata::{Key, TimeSignature};
+use super::{BeatsPerMinute, Component, Error, Position};
+use crate::sound::effects::{Delay, Filter};
+use crate::sound::mixer::Mix;
+use crate::sound::{Pitch, Velocity};
+
+#[derive(Debug)]
+pub struct InvalidValue {
+	/// What the value belongs to, e.g. `pellet 3`.
+	pub owner: String,
+	/// The component or pellet the value belongs to, if any.
+	pub place: Option<Place>,
+	pub field: &'static str,
+	pub value: Value,
+	pub expected: String,
+	/// Where the value is in the file, if it was read from JSON.
+	pub location: Option<Location>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Place {
+	Component(Position),
+	/// The index of the pellet in the project's list.
+	Pellet(usize),
+}
+
+impl Display for InvalidValue {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}: {} is {}, expected {}",
+			self.owner, self.field, self.value, self.expected
+		)
+	}
+}
+
+/// `text` is the document's JSON, if it was read from a JSON file, for locating the invalid values in it.
+pub fn check(document: &Value, text: Option<&str>) -> Result<(), Error> {
+	let mut invalid = Vec::new();
+
+	check_settings(&mut invalid, document);
+
+	let pellets = document.get("pellets").and_then(Value::as_array);
+	for (index, pellet) in pellets.into_iter().flatten().enumerate() {
+		let owner = || format!("pellet {index}");
+		let first = invalid.len();
+		check_pitch(&mut invalid, owner, pellet, "pitch");
+		check_velocity(&mut invalid, owner, pellet, "velocity");
+		place(&mut invalid[first..], Place::Pellet(index));
+	}
+
+	let instruments = document.get("instruments").and_then(Value::as_array);
+	for (index, instrument) in instruments.into_iter().flatten().enumerate() {
+		check_instrument(&mut invalid, index, instrument);
+	}
+
+	let components = document.get("components").and_then(Value::as_array);
+	for entry in components.into_iter().flatten() {
+		let (Some(position), Some(component)) = (entry.get(0), entry.get(1)) else {
+			continue;
+		};
+		let Some(ty) = component.get("_type").and_then(Value::as_str) else {
+			continue;
+		};
+		let at = Position::deserialize(position).ok();
+		if !Component::TYPES.contains(&ty) {
+			return Err(Error::UnknownComponent {
+				name: ty.to_owned(),
+				location: text
+					.zip(at)
+					.and_then(|(text, at)| locate(text, Place::Component(at), "_type")),
+			});
+		}
+		let owner = || format!("{ty} at ({}, {})", position["x"], position["y"]);
+
+		let first = invalid.len();
+		match ty {
+			"increment_pitch" => check_pitch(&mut invalid, owner, component, "current"),
+			"random_pitch" => {
+				check_pitch(&mut invalid, owner, component, "low");
+				check_pitch(&mut invalid, owner, component, "high");
+			}
+			"accent" | "attenuate" => check_velocity(&mut invalid, owner, component, "amount"),
+			"set_tempo" => check_tempo(&mut invalid, owner, component, "tempo"),
+			"scale_tempo" => check_number(
+				&mut invalid,
+				owner,
+				component,
+				"factor",
+				|value| {
+					(f64::from(*TEMPO_FACTORS.start())..=f64::from(*TEMPO_FACTORS.end())).contains(&value)
+				},
+				&format!("{} to {}", TEMPO_FACTORS.start(), TEMPO_FACTORS.end()),
+			),
+			"chance" => check_number(
+				&mut invalid,
+				owner,
+				component,
+				"probability",
+				|value| (0.0..=1.0).contains(&value),
+				"0 to 1",
+			),
+			"counter" => check_every(&mut invalid, owner, component, &["offset", "count"]),
+			"switch" => check_every(&mut invalid, owner, component, &["count"]),
+			_ => {}
+		}
+		if let Some(at) = at {
+			place(&mut invalid[first..], Place::Component(at));
+		}
+	}
+
+	if invalid.is_empty() {
+		Ok(())
+	} else {
+		if let Some(text) = text {
+			for value in &mut invalid {
+				value.location = value
+					.place
+					.and_then(|place| locate(text, place, value.field));
+			}
+		}
+		Err(Error::InvalidValues(invalid))
+	}
+}
+
+fn place(invalid: &mut [InvalidValue], place: Place) {
+	for value in invalid {
+		value.place = Some(place);
+	}
+}
+
+/// Finds `field` of the component or pellet at `place` in the JSON `text`.
+fn locate(text: &str, place: Place, field: &str) -> Option<Location> {
+	#[derive(Deserialize)]
+	struct Document<'a> {
+		#[serde(borrow, default)]
+		components: Vec<(Position, &'a RawValue)>,
+		#[serde(borrow, default)]
+		pellets: Vec<&'a RawValue>,
+	}
+
+	let document: Document<'_> = serde_json::from_str(text).ok()?;
+	let object = match place {
+		Place::Component(position) => {
+			document
+				.components
+				.iter()
+				.find(|&&(other, _)| other == position)?
+				.1
+		}
+		Place::Pellet(index) => document.pellets.get(index)?,
+	};
+	let fields: HashMap<String, &RawValue> = serde_json::from_str(object.get()).ok()?;
+	// the raw value borrows from `text`, so its address says where it is
+	let offset = (fields.get(field)?.get().as_ptr() as usize).checked_sub(text.as_ptr() as usize)?;
+	let before = text.get(..offset)?;
+	let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+	Location::in_text(
+		text,
+		before.matches('\n').count() + 1,
+		before[line_start..].chars().count() + 1,
+	)
+}
+
+fn check_pitch(
+	invalid: &mut Vec<InvalidValue>,
+	owner: impl FnOnce() -> String,
+	object: &Value,
+	field: &'static str,
+) {
+	check_number(
+		invalid,
+		owner,
+		object,
+		field,
+		|value| value.fract() == 0.0 && (0.0..=f64::from(Pitch::MAX)).contains(&value),
+		&format!("a whole number from 0 to {}", Pitch::MAX),
+	);
+}
+
+fn check_velocity(
+	invalid: &mut Vec<InvalidValue>,
+	owner: impl FnOnce() -> String,
+	object: &Value,
+	field: &'static str,
+) {
+	check_number(
+		invalid,
+		owner,
+		object,
+		field,
+		|value| value.fract() == 0.0 && (0.0..=f64::from(Velocity::MAX)).contains(&value),
+		&format!("a whole number from 0 to {}", Velocity::MAX),
+	);
+}
+
+/// Checks the project-wide fields.
+fn check_settings(invalid: &mut Vec<InvalidValue>, document: &Value) {
+	if let Some(metadata) = document.get("metadata") {
+		let owner = || "metadata".to_owned();
+		if let Some(time_signature) = metadata.get("time_signature") {
+			check_number(
+				invalid,
+				owner,
+				time_signature,
+				"beats",
+				|value| value >= 1.0,
+				"at least 1",
+			);
+			check_number(
+				invalid,
+				owner,
+				time_signature,
+				"note_value",
+				|value| TimeSignature::NOTE_VALUES.contains(&az::saturating_cast(value)),
+				&format!("one of {:?}", TimeSignature::NOTE_VALUES),
+			);
+		}
+		if let Some(key) = metadata.get("key") {
+			check_number(
+				invalid,
+				owner,
+				key,
+				"root",
+				|value| value < az::cast(Key::ROOT_NAMES.len()),
+				&format!("less than {}", Key::ROOT_NAMES.len()),
+			);
+		}
+	}
+
+	check_tempo(invalid, || "project".to_owned(), document, "tempo");
+	if let Some(groove) = document.get("groove") {
+		check_number(
+			invalid,
+			|| "groove".to_owned(),
+			groove,
+			"amount",
+			|value| (0.0..=1.0).contains(&value),
+			"0 to 1",
+		);
+	}
+
+	if let Some(mix) = document.get("mix") {
+		check_number(
+			invalid,
+			|| "mix".to_owned(),
+			mix,
+			"max_voices",
+			|value| value >= 1.0,
+			"at least 1",
+		);
+		let gains = mix.get("gains").and_then(Value::as_object);
+		for (instrument, gain) in gains.into_iter().flatten() {
+			if gain
+				.as_f64()
+				.is_some_and(|gain| !(0.0..=f64::from(Mix::MAX_GAIN)).contains(&gain))
+			{
+				invalid.push(InvalidValue {
+					owner: "mix".to_owned(),
+					place: None,
+					field: "gain",
+					value: gain.clone(),
+					expected: format!("0 to {} for {instrument}", Mix::MAX_GAIN),
+					location: None,
+				});
+			}
+		}
+		let pans = mix.get("pans").and_then(Value::as_object);
+		for (instrument, pan) in pans.into_iter().flatten() {
+			if pan.as_f64().is_some_and(|pan| !(-1.0..=1.0).contains(&pan)) {
+				invalid.push(InvalidValue {
+					owner: "mix".to_owned(),
+					place: None,
+					field: "pan",
+					value: pan.clone(),
+					expected: format!("-1 to 1 for {instrument}"),
+					location: None,
+				});
+			}
+		}
+		if let Some(panning) = mix.get("panning") {
+			check_panning(invalid, panning);
+		}
+		if let Some(effects) = mix.get("effects") {
+			check_effects(invalid, effects);
+		}
+	}
+}
+
+fn check_panning(invalid: &mut Vec<InvalidValue>, panning: &Value) {
+	if panning.get("mode").and_then(Value::as_str) != Some("span") {
+		return;
+	}
+	if let Some(left) = panning.get("left").and_then(Value::as_f64) {
+		check_number(
+			invalid,
+			|| "panning".to_owned(),
+			panning,
+			"right",
+			|right| right > left,
+			&format!("more than {left}, the left column"),
+		);
+	}
+}
+
+/// Checks that a counter or switch has an `every` of at least 1, and that each of `fields` is below it.
+fn check_every(
+	invalid: &mut Vec<InvalidValue>,
+	owner: impl Fn() -> String,
+	component: &Value,
+	fields: &[&'static str],
+) {
+	check_number(
+		invalid,
+		&owner,
+		component,
+		"every",
+		|every| every >= 1.0,
+		"at least 1",
+	);
+	let Some(every) = component
+		.get("every")
+		.and_then(Value::as_f64)
+		.filter(|&every| every >= 1.0)
+	else {
+		return;
+	};
+	for &field in fields {
+		check_number(
+			invalid,
+			&owner,
+			component,
+			field,
+			|value| value < every,
+			&format!("less than {every}, the every field"),
+		);
+	}
+}
+
+fn check_effects(invalid: &mut Vec<InvalidValue>, effects: &Value) {
+	let in_range = |[low, high]: [f32; 2]| {
+		(
+			move |value: f64| (f64::from(low)..=f64::from(high)).contains(&value),
+			format!("{low} to {high}"),
+		)
+	};
+	let checks = [
+		("delay", "beats", in_range(Delay::BEATS)),
+		("delay", "feedback", in_range([0.0, Delay::MAX_FEEDBACK])),
+		("delay", "mix", in_range([0.0, 1.0])),
+		("reverb", "room_size", in_range([0.0, 1.0])),
+		("reverb", "damping", in_range([0.0, 1.0])),
+		("reverb", "mix", in_range([0.0, 1.0])),
+		("low_pass", "cutoff", in_range(Filter::CUTOFFS)),
+		("high_pass", "cutoff", in_range(Filter::CUTOFFS)),
+	];
+	for (effect, field, (check, expected)) in checks {
+		if let Some(settings) = effects.get(effect) {
+			check_number(
+				invalid,
+				|| format!("{effect} effect"),
+				settings,
+				field,
+				check,
+				&expected,
+			);
+		}
+	}
+}
+
+fn check_instrument(invalid: &mut Vec<InvalidValue>, index: usize, instrument: &Value) {
+	let owner = || format!("instrument {index}");
+	check_number(
+		invalid,
+		owner,
+		instrument,
+		"gain",
+		|value| (0.0..=f64::from(Mix::MAX_GAIN)).contains(&value),
+		&format!("0 to {}", Mix::MAX_GAIN),
+	);
+	check_number(
+		invalid,
+		owner,
+		instrument,
+		"pan",
+		|value| (-1.0..=1.0).contains(&value),
+		"-1 to 1",
+	);
+	if let Some(envelope) = instrument.get("envelope") {
+		for field in ["attack", "release"] {
+			check_number(
+				invalid,
+				owner,
+				envelope,
+				field,
+				|value| value >= 0.0,
+				"at least 0",
+			);
+		}
+	}
+
+	let per_pitch = instrument
+		.get("samples")
+		.and_then(|samples| samples.get("per_pitch"));
+	if let Some(paths) = per_pitch.and_then(Value::as_array) {
+		let expected = usize::from(Pitch::MAX) + 1;
+		if paths.len() != expected {
+			invalid.push(InvalidValue {
+				owner: owner(),
+				place: None,
+				field: "per_pitch",
+				value: paths.len().into(),
+				expected: format!("{expected} files, one for every pitch"),
+				location: None,
+			});
+		}
+	}
+
+	let soundfont = instrument
+		.get("samples")
+		.and_then(|samples| samples.get("soundfont"));
+	if let Some(soundfont) = soundfont {
+		check_number(
+			invalid,
+			owner,
+			soundfont,
+			"bank",
+			|value| (0.0..=128.0).contains(&value),
+			"0 to 128",
+		);
+		check_number(
+			invalid,
+			owner,
+			soundfont,
+			"preset",
+			|value| (0.0..=127.0).contains(&value),
+			"0 to 127",
+		);
+		let highest = 127 - Pitch::MAX;
+		check_number(
+			invalid,
+			owner,
+			soundfont,
+			"key",
+			|value| (0.0..=f64::from(highest)).contains(&value),
+			&format!("0 to {highest}, so that every pitch is a MIDI key"),
+		);
+	}
+}
+
+fn check_tempo(
+	invalid: &mut Vec<InvalidValue>,
+	owner: impl FnOnce() -> String,
+	object: &Value,
+	field: &'static str,
+) {
+	let (min, max) = (BeatsPerMinute::MIN.0, BeatsPerMinute::MAX.0);
+	check_number(
+		invalid,
+		owner,
+		object,
+		field,
+		|value| (f64::from(min)..=f64::from(max)).contains(&value),
+		&format!("{min} to {max} beats per minute"),
+	);
+}
+
+/// Values that are missing or not numbers are left for deserialization to complain about.
+fn check_number(
+	invalid: &mut Vec<InvalidValue>,
+	owner: impl FnOnce() -> String,
+	object: &Value,
+	field: &'static str,
+	valid: impl FnOnce(f64) -> bool,
+	expected: &str,
+) {
+	let Some(value) = object.get(field) else {
+		return;
+	};
+	if value.as_f64().is_some_and(|number| !valid(number)) {
+		invalid.push(InvalidValue {
+			owner: owner(),
+			place: None,
+			field,
+			value: value.clone(),
+			expected: expected.to_owned(),
+			location: None,
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rejects_counters_and_switches_that_never_pass() {
+		let document = serde_json::json!({"components": [
+			[{"x": 0, "y": 0}, {"_type": "counter", "every": 0}],
+			[{"x": 1, "y": 0}, {"_type": "counter", "every": 4, "offset": 4, "count": 3}],
+			[{"x": 2, "y": 0}, {"_type": "switch", "every": 2, "count": 2}],
+			[{"x": 3, "y": 0}, {"_type": "switch", "every": 1}],
+		]});
+		let Err(Error::InvalidValues(invalid)) = check(&document, None) else {
+			panic!("the values should be invalid");
+		};
+		let fields: Vec<_> = invalid
+			.iter()
+			.map(|value| (value.owner.as_str(), value.field))
+			.collect();
+		assert_eq!(
+			fields,
+			[
+				("counter at (0, 0)", "every"),
+				("counter at (1, 0)", "offset"),
+				("switch at (2, 0)", "count"),
+			]
+		);
+	}
+
+	#[test]
+	fn locates_invalid_values_in_the_text() {
+		let text = r#"{"components": [
+	[{"x": 2, "y": -2}, {"_type": "random_pitch", "low": 0, "high": 99}]
+], "pellets": [{"x": 1, "y": 2, "pitch": 44, "direction": "up"}]}"#;
+		let Err(Error::InvalidValues(invalid)) =
+			check(&serde_json::from_str(text).unwrap(), Some(text))
+		else {
+			panic!("the values should be invalid");
+		};
+		let locations: Vec<_> = invalid
+			.iter()
+			.map(|value| {
+				let location = value.location.as_ref().unwrap();
+				(location.line, location.column)
+			})
+			.collect();
+		assert_eq!(locations, [(3, 42), (2, 66)]);
+	}
+}
@@ -0,0 +1,216 @@
+//! Samples decoded ahead of time, so that playing a note doesn't have to decode anything.
+
+use std::fmt::{self, Debug, Formatter};
+use std::io::{Cursor, Read, Seek};
+use std::sync::Arc;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use rodio::{Decoder, Source};
+
+use super::instrument::Envelope;
+use super::{Pitch, Sound, Type, Velocity};
+
+/// Every built-in sample, decoded on first use.
+#[allow(clippy::non_std_lazy_statics)] // once_cell is already a dependency and works on older toolchains
+static BANK: Lazy<Bank> = Lazy::new(Bank::decode);
+
+struct Bank {
+	guitar: Vec<Sample>,
+}
+
+impl Bank {
+	fn decode() -> Self {
+		Self {
+			guitar: (0..=Pitch::MAX)
+				.map(|pitch| {
+					Sample::decode(
+						Sound {
+							pitch: Pitch::new(pitch),
+							velocity: Velocity::DEFAULT,
+							ty: Type::Guitar,
+						}
+						.sample_for()
+						.unwrap(),
+					)
+				})
+				.collect(),
+		}
+	}
+}
+
+/// Decodes every sample now rather than when the first note plays.
+pub fn preload() {
+	Lazy::force(&BANK);
+}
+
+/// The decoded sample for `sound`, if it's built in.
+pub fn sample(sound: Sound) -> Option<&'static Sample> {
+	match sound.ty {
+		Type::Guitar => BANK.guitar.get(usize::from(sound.pitch.semitones())),
+		Type::Custom(_) => None,
+	}
+}
+
+/// A decoded sample, shared between all the notes playing it.
+#[derive(Clone)]
+pub struct Sample {
+	channels: u16,
+	/// Frames per second.
+	rate: u32,
+	data: Arc<[f32]>,
+}
+
+impl Sample {
+	fn decode(encoded: &'static [u8]) -> Self {
+		Self::from_decoder(Decoder::new_vorbis(Cursor::new(encoded)).unwrap())
+	}
+
+	/// `data` has the channels of each frame one after the other.
+	pub fn new(channels: u16, rate: u32, data: Vec<f32>) -> Self {
+		Self {
+			channels,
+			rate,
+			data: data.into(),
+		}
+	}
+
+	pub fn from_decoder<R: Read + Seek + Send + 'static>(decoder: Decoder<R>) -> Self {
+		Self {
+			channels: decoder.channels(),
+			rate: decoder.sample_rate(),
+			data: decoder.convert_samples().collect(),
+		}
+	}
+
+	fn frames(&self) -> usize {
+		self.data.len() / usize::from(self.channels.max(1))
+	}
+
+	/// The sample played `speed` times as fast, which also changes its pitch.
+	pub fn resampled(&self, speed: f32) -> Self {
+		let channels = usize::from(self.channels.max(1));
+		let frames = self.frames();
+		let new_frames = az::saturating_cast::<_, usize>(az::cast::<_, f32>(frames) / speed);
+		let at = |frame: usize, channel: usize| {
+			self
+				.data
+				.get(frame * channels + channel)
+				.copied()
+				.unwrap_or(0.0)
+		};
+
+		let mut data = Vec::with_capacity(new_frames * channels);
+		for frame in 0..new_frames {
+			// linear interpolation between the two nearest frames
+			let position = az::cast::<_, f32>(frame) * speed;
+			let before = az::saturating_cast::<_, usize>(position);
+			let weight = position.fract();
+			for channel in 0..channels {
+				data.push(at(before, channel) * (1.0 - weight) + at(before + 1, channel) * weight);
+			}
+		}
+		Self {
+			channels: self.channels,
+			rate: self.rate,
+			data: data.into(),
+		}
+	}
+
+	/// The sample made `gain` times as loud, and faded in and out according to `envelope`.
+	pub fn shaped(&self, gain: f32, envelope: Envelope) -> Self {
+		let channels = usize::from(self.channels.max(1));
+		let frames = self.frames();
+		let seconds_to_frames =
+			|seconds: f32| az::saturating_cast::<_, usize>(seconds * az::cast::<_, f32>(self.rate));
+		let attack = seconds_to_frames(envelope.attack).min(frames);
+		let release = seconds_to_frames(envelope.release).min(frames);
+
+		let data = self
+			.data
+			.iter()
+			.enumerate()
+			.map(|(index, sample)| {
+				let frame = index / channels;
+				let mut level = gain;
+				if frame < attack {
+					level *= az::cast::<_, f32>(frame) / az::cast::<_, f32>(attack);
+				}
+				let remaining = frames - frame;
+				if remaining < release {
+					level *= az::cast::<_, f32>(remaining) / az::cast::<_, f32>(release);
+				}
+				sample * level
+			})
+			.collect();
+		Self {
+			channels: self.channels,
+			rate: self.rate,
+			data,
+		}
+	}
+
+	pub fn source(&self) -> SampleSource {
+		SampleSource {
+			channels: self.channels,
+			sample_rate: self.rate,
+			data: Arc::clone(&self.data),
+			position: 0,
+		}
+	}
+}
+
+impl Debug for Sample {
+	// the data is far too long to be worth printing
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Sample")
+			.field("channels", &self.channels)
+			.field("rate", &self.rate)
+			.field("frames", &self.frames())
+			.finish_non_exhaustive()
+	}
+}
+
+/// Plays a [`Sample`] without copying it.
+pub struct SampleSource {
+	channels: u16,
+	sample_rate: u32,
+	data: Arc<[f32]>,
+	position: usize,
+}
+
+impl Iterator for SampleSource {
+	type Item = f32;
+
+	fn next(&mut self) -> Option<f32> {
+		let sample = self.data.get(self.position).copied();
+		self.position += 1;
+		sample
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let remaining = self.data.len().saturating_sub(self.position);
+		(remaining, Some(remaining))
+	}
+}
+
+impl Source for SampleSource {
+	fn current_frame_len(&self) -> Option<usize> {
+		None
+	}
+
+	fn channels(&self) -> u16 {
+		self.channels
+	}
+
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn total_duration(&self) -> Option<Duration> {
+		let frames = self.data.len() / usize::from(self.channels.max(1));
+		Some(Duration::from_secs_f64(
+			az::cast::<_, f64>(frames) / f64::from(self.sample_rate),
+		))
+	}
+}
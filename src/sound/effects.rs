@@ -0,0 +1,417 @@
+//! Effects applied to everything the mixer plays, in the order delay, reverb, then the filters.
+
+use std::f32::consts::{PI, SQRT_2};
+
+use serde::{Deserialize, Serialize};
+
+use super::mixer::SAMPLE_RATE;
+use crate::project::BeatsPerMinute;
+
+/// The longest echo the delay can hold, however slow the tempo.
+const MAX_DELAY_SECONDS: f32 = 4.0;
+/// The lengths of the reverb's comb filters in frames, from Freeverb.
+const COMB_FRAMES: [usize; 4] = [1116, 1188, 1277, 1356];
+/// The lengths of the reverb's allpass filters in frames, from Freeverb.
+const ALLPASS_FRAMES: [usize; 2] = [556, 441];
+/// How much longer the right channel's reverb filters are, so the two channels don't sound identical.
+const STEREO_SPREAD: usize = 23;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Effects {
+	pub delay: Delay,
+	pub reverb: Reverb,
+	pub low_pass: Filter,
+	pub high_pass: Filter,
+}
+
+/// Repeats everything after a number of beats, each echo quieter than the last.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Delay {
+	pub enabled: bool,
+	/// The time between echoes.
+	pub beats: f32,
+	/// How loud each echo is compared to the one before, from 0 to [`Delay::MAX_FEEDBACK`].
+	pub feedback: f32,
+	/// How loud the echoes are compared to the original, from 0 to 1.
+	pub mix: f32,
+}
+
+impl Delay {
+	pub const BEATS: [f32; 2] = [0.125, 4.0];
+	/// Any more and the echoes would take too long to die out.
+	pub const MAX_FEEDBACK: f32 = 0.95;
+}
+
+impl Default for Delay {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			beats: 0.75,
+			feedback: 0.35,
+			mix: 0.3,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Reverb {
+	pub enabled: bool,
+	/// How long the reverb rings for, from 0 to 1.
+	pub room_size: f32,
+	/// How quickly high frequencies die out, from 0 to 1.
+	pub damping: f32,
+	/// How loud the reverb is compared to the original, from 0 to 1.
+	pub mix: f32,
+}
+
+impl Default for Reverb {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			room_size: 0.5,
+			damping: 0.5,
+			mix: 0.25,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filter {
+	pub enabled: bool,
+	/// The frequency in hertz past which the filter cuts.
+	pub cutoff: f32,
+}
+
+impl Filter {
+	pub const CUTOFFS: [f32; 2] = [20.0, 20_000.0];
+}
+
+impl Default for Filter {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			cutoff: 1000.0,
+		}
+	}
+}
+
+/// The effects' state for both channels.
+pub struct Chain {
+	effects: Effects,
+	delay: [DelayLine; 2],
+	/// The number of frames between echoes.
+	delay_frames: usize,
+	reverb: [Freeverb; 2],
+	low_pass: [Biquad; 2],
+	high_pass: [Biquad; 2],
+}
+
+impl Chain {
+	pub fn new() -> Self {
+		let max_delay =
+			az::saturating_cast::<_, usize>(MAX_DELAY_SECONDS * az::cast::<_, f32>(SAMPLE_RATE));
+		Self {
+			effects: Effects::default(),
+			delay: [DelayLine::new(max_delay), DelayLine::new(max_delay)],
+			delay_frames: 1,
+			reverb: [Freeverb::new(0), Freeverb::new(STEREO_SPREAD)],
+			low_pass: [Biquad::default(); 2],
+			high_pass: [Biquad::default(); 2],
+		}
+	}
+
+	/// `tempo` is what the delay is synced to.
+	pub fn configure(&mut self, effects: Effects, tempo: BeatsPerMinute) {
+		let seconds = effects.delay.beats * tempo.beat_time().as_secs_f32();
+		self.delay_frames = az::saturating_cast::<_, usize>(seconds * az::cast::<_, f32>(SAMPLE_RATE))
+			.clamp(1, self.delay[0].buffer.len());
+		let low_pass = Biquad::low_pass(effects.low_pass.cutoff);
+		let high_pass = Biquad::high_pass(effects.high_pass.cutoff);
+		for channel in 0..2 {
+			self.low_pass[channel].set_coefficients(low_pass);
+			self.high_pass[channel].set_coefficients(high_pass);
+		}
+		self.effects = effects;
+	}
+
+	pub fn process(&mut self, frame: &mut [f32; 2]) {
+		let Effects {
+			delay,
+			reverb,
+			low_pass,
+			high_pass,
+		} = self.effects;
+		for (channel, sample) in frame.iter_mut().enumerate() {
+			if delay.enabled {
+				let line = &mut self.delay[channel];
+				let echo = line.read(self.delay_frames);
+				line.write(*sample + echo * delay.feedback);
+				*sample += echo * delay.mix;
+			}
+			if reverb.enabled {
+				*sample += self.reverb[channel].process(*sample, reverb) * reverb.mix;
+			}
+			if low_pass.enabled {
+				*sample = self.low_pass[channel].process(*sample);
+			}
+			if high_pass.enabled {
+				*sample = self.high_pass[channel].process(*sample);
+			}
+		}
+	}
+}
+
+struct DelayLine {
+	buffer: Vec<f32>,
+	/// Where the next sample is written.
+	position: usize,
+}
+
+impl DelayLine {
+	fn new(len: usize) -> Self {
+		Self {
+			buffer: vec![0.0; len],
+			position: 0,
+		}
+	}
+
+	/// The sample written `frames` ago.
+	fn read(&self, frames: usize) -> f32 {
+		let len = self.buffer.len();
+		self.buffer[(self.position + len - frames % len) % len]
+	}
+
+	fn write(&mut self, sample: f32) {
+		self.buffer[self.position] = sample;
+		self.position = (self.position + 1) % self.buffer.len();
+	}
+}
+
+/// A simple Schroeder reverb tuned like Freeverb: parallel comb filters, then allpass filters in series.
+struct Freeverb {
+	combs: Vec<(DelayLine, f32)>,
+	allpasses: Vec<DelayLine>,
+}
+
+impl Freeverb {
+	/// `spread` is added to the length of every filter.
+	fn new(spread: usize) -> Self {
+		Self {
+			combs: COMB_FRAMES
+				.iter()
+				.map(|frames| (DelayLine::new(frames + spread), 0.0))
+				.collect(),
+			allpasses: ALLPASS_FRAMES
+				.iter()
+				.map(|frames| DelayLine::new(frames + spread))
+				.collect(),
+		}
+	}
+
+	fn process(&mut self, input: f32, settings: Reverb) -> f32 {
+		let feedback = 0.7 + 0.28 * settings.room_size.clamp(0.0, 1.0);
+		let damping = settings.damping.clamp(0.0, 1.0) * 0.4;
+		// scaled down, since the combs add up
+		let input = input * 0.09;
+
+		let mut output = 0.0;
+		for (line, filtered) in &mut self.combs {
+			let delayed = line.read(line.buffer.len());
+			*filtered = delayed * (1.0 - damping) + *filtered * damping;
+			line.write(input + *filtered * feedback);
+			output += delayed;
+		}
+		for line in &mut self.allpasses {
+			let delayed = line.read(line.buffer.len());
+			line.write(output + delayed * 0.5);
+			output = delayed - output;
+		}
+		output
+	}
+}
+
+/// A second-order filter, with coefficients from the Audio EQ Cookbook.
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+	coefficients: Coefficients,
+	/// The last two inputs and outputs.
+	inputs: [f32; 2],
+	outputs: [f32; 2],
+}
+
+/// Normalized so that `a0` is 1.
+#[derive(Debug, Clone, Copy, Default)]
+struct Coefficients {
+	b: [f32; 3],
+	a: [f32; 2],
+}
+
+impl Biquad {
+	fn low_pass(cutoff: f32) -> Coefficients {
+		let (cos, alpha) = Self::shape(cutoff);
+		Self::normalize(
+			[(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+			cos,
+			alpha,
+		)
+	}
+
+	#[allow(clippy::manual_midpoint)] // written like the cookbook, and f32::midpoint needs Rust 1.85
+	fn high_pass(cutoff: f32) -> Coefficients {
+		let (cos, alpha) = Self::shape(cutoff);
+		Self::normalize(
+			[(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+			cos,
+			alpha,
+		)
+	}
+
+	/// The cosine of the cutoff's angular frequency, and the alpha for a Butterworth response.
+	fn shape(cutoff: f32) -> (f32, f32) {
+		let nyquist = az::cast::<_, f32>(SAMPLE_RATE) / 2.0;
+		let omega =
+			2.0 * PI * cutoff.clamp(Filter::CUTOFFS[0], nyquist * 0.99) / az::cast::<_, f32>(SAMPLE_RATE);
+		(omega.cos(), omega.sin() / SQRT_2)
+	}
+
+	fn normalize(b: [f32; 3], cos: f32, alpha: f32) -> Coefficients {
+		let a0 = 1.0 + alpha;
+		Coefficients {
+			b: b.map(|b| b / a0),
+			a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+		}
+	}
+
+	fn set_coefficients(&mut self, coefficients: Coefficients) {
+		self.coefficients = coefficients;
+	}
+
+	fn process(&mut self, input: f32) -> f32 {
+		let Coefficients { b, a } = self.coefficients;
+		let output = b[0] * input + b[1] * self.inputs[0] + b[2] * self.inputs[1]
+			- a[0] * self.outputs[0]
+			- a[1] * self.outputs[1];
+		self.inputs = [input, self.inputs[0]];
+		self.outputs = [output, self.outputs[0]];
+		output
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SECOND: usize = SAMPLE_RATE as usize;
+
+	const IMPULSE: [f32; 1] = [1.0];
+	const DC: [f32; 1] = [1.0];
+	/// The highest frequency there is, half the sample rate.
+	const NYQUIST: [f32; 2] = [1.0, -1.0];
+
+	/// What the chain plays in the left channel for `input` in both, followed by silence until there are `frames` frames.
+	fn response(effects: Effects, input: &[f32], frames: usize) -> Vec<f32> {
+		let mut chain = Chain::new();
+		chain.configure(effects, BeatsPerMinute(120.0));
+		(0..frames)
+			.map(|frame| {
+				let sample = input.get(frame).copied().unwrap_or(0.0);
+				let mut frame = [sample; 2];
+				chain.process(&mut frame);
+				frame[0]
+			})
+			.collect()
+	}
+
+	#[test]
+	fn delay_echoes_on_the_beat() {
+		let delay = Delay {
+			enabled: true,
+			beats: 1.0,
+			feedback: 0.5,
+			mix: 0.8,
+		};
+		let output = response(
+			Effects {
+				delay,
+				..Effects::default()
+			},
+			&IMPULSE,
+			50_000,
+		);
+		// a beat at 120 BPM is half a second
+		let beat = SECOND / 2;
+		let close = |sample: f32, expected: f32| (sample - expected).abs() < 1e-6;
+		assert!(close(output[0], 1.0));
+		assert!(close(output[beat], 0.8));
+		assert!(close(output[2 * beat], 0.4));
+		let echoes = output.iter().filter(|&&sample| sample != 0.0).count();
+		assert_eq!(echoes, 3);
+	}
+
+	#[test]
+	fn reverb_rings_after_the_shortest_comb_and_dies_out() {
+		let reverb = Reverb {
+			enabled: true,
+			..Reverb::default()
+		};
+		let output = response(
+			Effects {
+				reverb,
+				..Effects::default()
+			},
+			&IMPULSE,
+			10 * SECOND,
+		);
+		assert!(output[1..COMB_FRAMES[0]]
+			.iter()
+			.all(|&sample| sample == 0.0));
+		let loudness = |frames: &[f32]| frames.iter().map(|sample| sample.abs()).fold(0.0, f32::max);
+		let early = loudness(&output[1..SECOND]);
+		let late = loudness(&output[9 * SECOND..]);
+		assert!(early > 0.01, "{early}");
+		assert!(
+			late < early / 1000.0,
+			"{late} is not much quieter than {early}"
+		);
+	}
+
+	/// How loud the chain's output is once it has settled, for `pattern` played over and over.
+	fn filter_gain(effects: Effects, pattern: &[f32]) -> f32 {
+		let input: Vec<f32> = pattern.iter().copied().cycle().take(10_000).collect();
+		let output = response(effects, &input, input.len());
+		output[9_000..]
+			.iter()
+			.map(|sample| sample.abs())
+			.fold(0.0, f32::max)
+	}
+
+	#[test]
+	fn low_pass_keeps_dc_and_cuts_highs() {
+		let effects = Effects {
+			low_pass: Filter {
+				enabled: true,
+				cutoff: 1000.0,
+			},
+			..Effects::default()
+		};
+		assert!((filter_gain(effects, &DC) - 1.0).abs() < 0.001);
+		assert!(filter_gain(effects, &NYQUIST) < 0.001);
+	}
+
+	#[test]
+	fn high_pass_cuts_dc_and_keeps_highs() {
+		let effects = Effects {
+			high_pass: Filter {
+				enabled: true,
+				cutoff: 1000.0,
+			},
+			..Effects::default()
+		};
+		assert!(filter_gain(effects, &DC) < 0.001);
+		assert!((filter_gain(effects, &NYQUIST) - 1.0).abs() < 0.001);
+	}
+}
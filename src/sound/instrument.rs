@@ -0,0 +1,129 @@
+//! Instruments made from sample files on disk, defined in the project that uses them.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use rodio::Decoder;
+use serde::{Deserialize, Serialize};
+
+use super::bank::Sample;
+use super::mixer::SAMPLE_RATE;
+use super::soundfont::SoundFont;
+use super::Pitch;
+use crate::project::Error;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Instrument {
+	pub name: String,
+	pub samples: Samples,
+	#[serde(default = "default_gain")]
+	pub gain: f32,
+	#[serde(default)]
+	pub envelope: Envelope,
+	/// Where the instrument is placed between the speakers, from -1 for left to 1 for right.
+	#[serde(default)]
+	pub pan: f32,
+}
+
+const fn default_gain() -> f32 {
+	1.0
+}
+
+/// The files an instrument plays. Paths are relative to the project file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Samples {
+	/// A file for every pitch, lowest first.
+	PerPitch(Vec<PathBuf>),
+	/// One file recorded at `pitch`, which is sped up or slowed down for the others.
+	Root { path: PathBuf, pitch: Pitch },
+	/// A preset from an SF2 file, with the lowest pitch playing MIDI key `key`.
+	#[serde(rename = "soundfont")]
+	SoundFont {
+		path: PathBuf,
+		#[serde(default)]
+		bank: u16,
+		preset: u16,
+		#[serde(default = "default_key")]
+		key: u8,
+	},
+}
+
+/// Middle C.
+const fn default_key() -> u8 {
+	60
+}
+
+/// How a note fades in and out, in seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Envelope {
+	pub attack: f32,
+	pub release: f32,
+}
+
+impl Instrument {
+	/// Decodes the instrument's samples, one for every pitch. `directory` is where the project file is.
+	pub fn load(&self, directory: &Path) -> Result<Vec<Sample>, Error> {
+		let samples: Vec<Sample> = match &self.samples {
+			Samples::PerPitch(paths) => paths
+				.iter()
+				.map(|path| load_sample(&directory.join(path)))
+				.collect::<Result<_, _>>()?,
+			Samples::Root { path, pitch } => {
+				let root = load_sample(&directory.join(path))?;
+				(0..=Pitch::MAX)
+					.map(|other| {
+						let semitones = f32::from(other) - f32::from(pitch.semitones());
+						root.resampled(2.0_f32.powf(semitones / 12.0))
+					})
+					.collect()
+			}
+			Samples::SoundFont {
+				path,
+				bank,
+				preset,
+				key,
+			} => load_soundfont(&directory.join(path), *bank, *preset, *key)?,
+		};
+		Ok(
+			samples
+				.into_iter()
+				.map(|sample| sample.shaped(self.gain, self.envelope))
+				.collect(),
+		)
+	}
+}
+
+fn load_sample(path: &Path) -> Result<Sample, Error> {
+	let file = File::open(path).map_err(Error::io(path))?;
+	let decoder = Decoder::new(BufReader::new(file)).map_err(|error| Error::Sample {
+		path: path.to_owned(),
+		message: error.to_string(),
+	})?;
+	Ok(Sample::from_decoder(decoder))
+}
+
+/// Keys the preset has nothing for are silent.
+fn load_soundfont(path: &Path, bank: u16, preset: u16, key: u8) -> Result<Vec<Sample>, Error> {
+	let error = |message| Error::Sample {
+		path: path.to_owned(),
+		message,
+	};
+	let file = std::fs::read(path).map_err(Error::io(path))?;
+	let soundfont = SoundFont::parse(&file).map_err(error)?;
+	if !soundfont.has_preset(bank, preset) {
+		return Err(error(format!("there is no preset {preset} in bank {bank}")));
+	}
+	Ok(
+		(0..=Pitch::MAX)
+			.map(|pitch| {
+				soundfont
+					.note(bank, preset, key.saturating_add(pitch))
+					.unwrap_or_else(|| Sample::new(1, SAMPLE_RATE, Vec::new()))
+			})
+			.collect(),
+	)
+}
@@ -0,0 +1,402 @@
+//! Mixes the notes being played into a single stream, so that how many play at once and how loud they get can be kept in check.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::source::UniformSourceIterator;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+
+use super::bank::{Sample, SampleSource};
+use super::effects::{Chain, Effects};
+use super::{Note, Type};
+use crate::project::BeatsPerMinute;
+
+const CHANNELS: u16 = 2;
+pub const SAMPLE_RATE: u32 = 44_100;
+/// How many frames a stolen voice takes to fade out, so that it doesn't click.
+const STEAL_FADE_FRAMES: u32 = 256;
+/// Where the limiter starts to compress.
+const LIMITER_THRESHOLD: f32 = 0.8;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mix {
+	/// How many notes each instrument can play at once. Starting another stops its oldest note.
+	pub max_voices: u8,
+	/// The volume of each instrument by name, if it isn't 1.
+	pub gains: BTreeMap<String, f32>,
+	/// Where each instrument by name is placed between the speakers, from -1 for left to 1 for right, if it isn't centred.
+	pub pans: BTreeMap<String, f32>,
+	/// Whether loud passages are softly limited instead of clipping.
+	pub limiter: bool,
+	/// Whether notes are also panned by where they were played.
+	pub panning: Panning,
+	/// Applied to everything played, before the limiter.
+	pub effects: Effects,
+}
+
+/// Whether notes are also placed between the speakers by where they were played on the grid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "mode")]
+pub enum Panning {
+	/// Only instruments' own pans are used.
+	#[default]
+	Instrument,
+	/// From the left edge of the editor to its right edge.
+	Viewport,
+	/// From column `left` to column `right`, which is further right.
+	Span { left: i16, right: i16 },
+}
+
+impl Panning {
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Instrument => "By instrument",
+			Self::Viewport => "Across the editor",
+			Self::Span { .. } => "Across columns",
+		}
+	}
+}
+
+impl Default for Mix {
+	fn default() -> Self {
+		Self {
+			max_voices: 16,
+			gains: BTreeMap::new(),
+			pans: BTreeMap::new(),
+			limiter: true,
+			panning: Panning::default(),
+			effects: Effects::default(),
+		}
+	}
+}
+
+impl Mix {
+	pub const MAX_GAIN: f32 = 4.0;
+
+	/// Custom instruments have their own gain instead.
+	pub fn gain(&self, ty: Type) -> f32 {
+		ty.name()
+			.and_then(|name| self.gains.get(name))
+			.copied()
+			.unwrap_or(1.0)
+	}
+
+	/// Custom instruments have their own pan instead.
+	pub fn pan(&self, ty: Type) -> f32 {
+		ty.name()
+			.and_then(|name| self.pans.get(name))
+			.copied()
+			.unwrap_or(0.0)
+	}
+}
+
+struct Scheduled {
+	frame: u64,
+	ty: Type,
+	sample: SampleSource,
+	/// How loud the note is played, besides its instrument's gain.
+	level: f32,
+	pan: f32,
+}
+
+enum Command {
+	/// Starts a sound on a bus at the given frame.
+	Play(u64, Scheduled),
+	Configure(u64, Mix, BeatsPerMinute),
+	/// Stops everything on a bus, since its mixer is gone.
+	Remove(u64),
+}
+
+/// Adds mixers to a [`MixerSource`] playing on another thread. Every mixer is played from the same source, so they all share one clock.
+pub struct Output {
+	commands: Sender<Command>,
+	/// How many frames the source has mixed.
+	position: Arc<AtomicU64>,
+	/// The bus of the next mixer.
+	next_bus: u64,
+}
+
+impl Output {
+	/// The source has to be played for any mixer to be heard.
+	pub fn new() -> (Self, MixerSource) {
+		let (send, recv) = channel();
+		let position = Arc::new(AtomicU64::new(0));
+		let output = Self {
+			commands: send,
+			position: Arc::clone(&position),
+			next_bus: 0,
+		};
+		let source = MixerSource {
+			commands: recv,
+			position,
+			buses: BTreeMap::new(),
+			frame: [0.0; CHANNELS as usize],
+			channel: 0,
+		};
+		(output, source)
+	}
+
+	/// A mixer with its own settings and effects, which stops playing when it's dropped.
+	pub fn mixer(&mut self) -> Mixer {
+		let bus = self.next_bus;
+		self.next_bus += 1;
+		Mixer {
+			bus,
+			commands: self.commands.clone(),
+			mix: Mix::default(),
+			tempo: BeatsPerMinute(0.0),
+			position: Arc::clone(&self.position),
+		}
+	}
+}
+
+/// Sends notes to its bus of a [`MixerSource`].
+pub struct Mixer {
+	bus: u64,
+	commands: Sender<Command>,
+	/// The settings last sent to the source.
+	mix: Mix,
+	tempo: BeatsPerMinute,
+	/// How many frames the source has mixed.
+	position: Arc<AtomicU64>,
+}
+
+impl Mixer {
+	/// `tempo` is what the delay effect is synced to.
+	pub fn configure(&mut self, mix: &Mix, tempo: BeatsPerMinute) {
+		if *mix != self.mix || tempo != self.tempo {
+			self.mix = mix.clone();
+			self.tempo = tempo;
+			// the source only stops when the audio output does, and then there's nothing to configure anyway
+			let _ = self
+				.commands
+				.send(Command::Configure(self.bus, mix.clone(), tempo));
+		}
+	}
+
+	/// How many frames the source has mixed so far.
+	pub fn position(&self) -> u64 {
+		self.position.load(Ordering::Relaxed)
+	}
+
+	/// Plays `sample` for `note` starting at `frame`, or right away if that has passed.
+	pub fn play_at(&self, note: Note, sample: &Sample, frame: u64) {
+		let _ = self.commands.send(Command::Play(
+			self.bus,
+			Scheduled {
+				frame,
+				ty: note.sound.ty,
+				sample: sample.source(),
+				level: note.sound.velocity.gain(),
+				pan: note.pan,
+			},
+		));
+	}
+}
+
+impl Drop for Mixer {
+	fn drop(&mut self) {
+		let _ = self.commands.send(Command::Remove(self.bus));
+	}
+}
+
+struct Voice {
+	ty: Type,
+	samples: UniformSourceIterator<SampleSource, f32>,
+	/// How loud the note was played.
+	level: f32,
+	/// `level` combined with the instrument's gain.
+	gain: f32,
+	/// How much of the voice goes to each channel.
+	balance: [f32; CHANNELS as usize],
+	/// How many frames are left before the voice is silent, if it's been stolen.
+	fade: Option<u32>,
+}
+
+/// What one [`Mixer`] is playing.
+struct Bus {
+	mix: Mix,
+	effects: Chain,
+	/// Sounds waiting for their frame, in the order they were sent.
+	scheduled: VecDeque<Scheduled>,
+	/// From oldest to newest.
+	voices: Vec<Voice>,
+}
+
+impl Bus {
+	fn new() -> Self {
+		Self {
+			mix: Mix::default(),
+			effects: Chain::new(),
+			scheduled: VecDeque::new(),
+			voices: Vec::new(),
+		}
+	}
+
+	fn configure(&mut self, mix: Mix, tempo: BeatsPerMinute) {
+		for voice in &mut self.voices {
+			voice.gain = mix.gain(voice.ty) * voice.level;
+		}
+		self.effects.configure(mix.effects, tempo);
+		self.mix = mix;
+	}
+
+	fn start(&mut self, scheduled: Scheduled) {
+		let Scheduled {
+			ty,
+			sample,
+			level,
+			pan,
+			..
+		} = scheduled;
+		let is_playing = |voice: &&mut Voice| voice.ty == ty && voice.fade.is_none();
+		let playing = self.voices.iter_mut().filter(is_playing).count();
+		if playing >= usize::from(self.mix.max_voices.max(1)) {
+			if let Some(oldest) = self.voices.iter_mut().find(is_playing) {
+				oldest.fade = Some(STEAL_FADE_FRAMES);
+			}
+		}
+
+		self.voices.push(Voice {
+			ty,
+			samples: UniformSourceIterator::new(sample, CHANNELS, SAMPLE_RATE),
+			level,
+			gain: self.mix.gain(ty) * level,
+			balance: balance(pan),
+			fade: None,
+		});
+	}
+
+	fn mix_frame(&mut self, position: u64) -> [f32; CHANNELS as usize] {
+		while let Some(scheduled) = self.scheduled.front() {
+			if scheduled.frame > position {
+				break;
+			}
+			let scheduled = self.scheduled.pop_front().unwrap();
+			self.start(scheduled);
+		}
+		let mut frame = [0.0; CHANNELS as usize];
+		self.voices.retain_mut(|voice| {
+			let mut gain = voice.gain;
+			if let Some(fade) = &mut voice.fade {
+				if *fade == 0 {
+					return false;
+				}
+				*fade -= 1;
+				gain *= az::cast::<_, f32>(*fade) / az::cast::<_, f32>(STEAL_FADE_FRAMES);
+			}
+			for (output, balance) in frame.iter_mut().zip(voice.balance) {
+				let Some(sample) = voice.samples.next() else {
+					return false;
+				};
+				*output += sample * gain * balance;
+			}
+			true
+		});
+
+		self.effects.process(&mut frame);
+		if self.mix.limiter {
+			for output in &mut frame {
+				*output = soft_limit(*output);
+			}
+		}
+		frame
+	}
+}
+
+/// Mixes the buses of every [`Mixer`] together.
+pub struct MixerSource {
+	commands: Receiver<Command>,
+	position: Arc<AtomicU64>,
+	buses: BTreeMap<u64, Bus>,
+	frame: [f32; CHANNELS as usize],
+	/// The channel of `frame` to output next.
+	channel: usize,
+}
+
+impl MixerSource {
+	fn receive(&mut self) {
+		while let Ok(command) = self.commands.try_recv() {
+			match command {
+				Command::Play(bus, scheduled) => self
+					.buses
+					.entry(bus)
+					.or_insert_with(Bus::new)
+					.scheduled
+					.push_back(scheduled),
+				Command::Configure(bus, mix, tempo) => self
+					.buses
+					.entry(bus)
+					.or_insert_with(Bus::new)
+					.configure(mix, tempo),
+				Command::Remove(bus) => {
+					self.buses.remove(&bus);
+				}
+			}
+		}
+	}
+
+	fn mix_frame(&mut self) {
+		self.receive();
+		let position = self.position.fetch_add(1, Ordering::Relaxed);
+		self.frame = [0.0; CHANNELS as usize];
+		for bus in self.buses.values_mut() {
+			for (output, sample) in self.frame.iter_mut().zip(bus.mix_frame(position)) {
+				*output += sample;
+			}
+		}
+	}
+}
+
+/// How much of a note at `pan` goes to the left and right channels. Centred notes play at full volume in both.
+fn balance(pan: f32) -> [f32; CHANNELS as usize] {
+	let pan = pan.clamp(-1.0, 1.0);
+	[(1.0 - pan).min(1.0), (1.0 + pan).min(1.0)]
+}
+
+/// Leaves quiet samples alone, and smoothly squashes louder ones so they never go past 1.
+fn soft_limit(sample: f32) -> f32 {
+	let magnitude = sample.abs();
+	if magnitude <= LIMITER_THRESHOLD {
+		return sample;
+	}
+	let headroom = 1.0 - LIMITER_THRESHOLD;
+	let limited = LIMITER_THRESHOLD + headroom * ((magnitude - LIMITER_THRESHOLD) / headroom).tanh();
+	limited.copysign(sample)
+}
+
+impl Iterator for MixerSource {
+	type Item = f32;
+
+	fn next(&mut self) -> Option<f32> {
+		if self.channel == 0 {
+			self.mix_frame();
+		}
+		let sample = self.frame[self.channel];
+		self.channel = (self.channel + 1) % self.frame.len();
+		Some(sample)
+	}
+}
+
+impl Source for MixerSource {
+	fn current_frame_len(&self) -> Option<usize> {
+		None
+	}
+
+	fn channels(&self) -> u16 {
+		CHANNELS
+	}
+
+	fn sample_rate(&self) -> u32 {
+		SAMPLE_RATE
+	}
+
+	fn total_duration(&self) -> Option<Duration> {
+		None
+	}
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+pub mod bank;
+pub mod effects;
+pub mod instrument;
+pub mod mixer;
+pub mod schedule;
+pub mod soundfont;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8")]
+pub struct Pitch(u8);
+
+impl TryFrom<u8> for Pitch {
+	type Error = String;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		if value <= Self::MAX {
+			Ok(Self(value))
+		} else {
+			Err(format!(
+				"invalid pitch {value}, expected at most {}",
+				Self::MAX
+			))
+		}
+	}
+}
+
+impl Pitch {
+	pub const MAX: u8 = 7;
+
+	pub const fn new(value: u8) -> Self {
+		assert!(value <= Self::MAX);
+		Self(value)
+	}
+
+	pub const fn increment_by(self, amount: u8) -> Self {
+		Self((self.0 + amount) % (Self::MAX + 1))
+	}
+
+	pub const fn semitones(self) -> u8 {
+		self.0
+	}
+}
+
+/// How hard a note is played, as a MIDI velocity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8")]
+pub struct Velocity(u8);
+
+impl TryFrom<u8> for Velocity {
+	type Error = String;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		if value <= Self::MAX {
+			Ok(Self(value))
+		} else {
+			Err(format!(
+				"invalid velocity {value}, expected at most {}",
+				Self::MAX
+			))
+		}
+	}
+}
+
+impl Default for Velocity {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+impl Velocity {
+	pub const MAX: u8 = 127;
+	/// What pellets start with. Notes at this velocity play at their instrument's own volume.
+	pub const DEFAULT: Self = Self(96);
+
+	pub fn raised_by(self, amount: u8) -> Self {
+		Self(self.0.saturating_add(amount).min(Self::MAX))
+	}
+
+	pub const fn lowered_by(self, amount: u8) -> Self {
+		Self(self.0.saturating_sub(amount))
+	}
+
+	/// How loud a note at this velocity is, compared to one at the default.
+	pub fn gain(self) -> f32 {
+		f32::from(self.0) / f32::from(Self::DEFAULT.0)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+	Guitar,
+	/// One of the project's own instruments, by index.
+	Custom(u8),
+}
+
+impl Type {
+	/// The built-in instruments.
+	pub const ALL: [Self; 1] = [Self::Guitar];
+
+	/// How the instrument is referred to in project files, if it's built in.
+	pub fn name(self) -> Option<&'static str> {
+		match self {
+			Self::Guitar => Some("guitar"),
+			Self::Custom(_) => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sound {
+	pub pitch: Pitch,
+	pub velocity: Velocity,
+	pub ty: Type,
+}
+
+/// A sound, and when playback got to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Note {
+	pub sound: Sound,
+	/// The tick the note starts on.
+	pub tick: u64,
+	/// Where the note is placed between the speakers, from -1 for left to 1 for right.
+	pub pan: f32,
+}
+
+impl Sound {
+	/// The encoded sample, if the instrument is built in.
+	pub fn sample_for(self) -> Option<&'static [u8]> {
+		match self.ty {
+			Type::Guitar => Some(
+				[
+					include_bytes!("../../sounds/guitar-0.ogg") as &[u8],
+					include_bytes!("../../sounds/guitar-1.ogg"),
+					include_bytes!("../../sounds/guitar-2.ogg"),
+					include_bytes!("../../sounds/guitar-3.ogg"),
+					include_bytes!("../../sounds/guitar-4.ogg"),
+					include_bytes!("../../sounds/guitar-5.ogg"),
+					include_bytes!("../../sounds/guitar-6.ogg"),
+					include_bytes!("../../sounds/guitar-7.ogg"),
+				][usize::from(self.pitch.semitones())],
+			),
+			Type::Custom(_) => None,
+		}
+	}
+}
@@ -0,0 +1,92 @@
+//! Keeps playback in step with the audio output, so that every note starts on exactly the right frame.
+
+use super::mixer::{Mixer, SAMPLE_RATE};
+use crate::project::Project;
+
+/// How many frames of audio playback is run ahead by. Notes are queued this far in advance, so the stepper thread waking up late doesn't delay them.
+const LOOKAHEAD_FRAMES: u64 = SAMPLE_RATE as u64 / 20;
+const FRAMES_PER_TICK: u64 = SAMPLE_RATE as u64 / 60;
+/// The most ticks to catch up on at once, in case the audio output stalled.
+const MAX_TICKS_PER_RUN: u32 = 30;
+
+#[derive(Debug, Default)]
+pub struct Schedule {
+	/// A tick and the frame it was played on, from when playback was last started.
+	origin: Option<(u64, u64)>,
+}
+
+impl Schedule {
+	fn frame_of(origin: (u64, u64), tick: u64) -> u64 {
+		let (origin_tick, origin_frame) = origin;
+		origin_frame + tick.saturating_sub(origin_tick) * FRAMES_PER_TICK
+	}
+
+	/// The frame playback was last started on, while it's playing.
+	pub fn origin_frame(&self) -> Option<u64> {
+		self.origin.map(|(_, frame)| frame)
+	}
+
+	/// Steps `project` up to the lookahead, queueing its notes on `mixer`.
+	/// When playback starts, its ticks line up with those of the schedule started on `in_time_with`, if any.
+	pub fn run(&mut self, project: &mut Project, mixer: &Mixer, in_time_with: Option<u64>) {
+		// paused, or stopped and started again since the last run
+		if project.paused || self.origin.is_some_and(|(tick, _)| project.tick() < tick) {
+			self.origin = None;
+		}
+		if project.paused {
+			return;
+		}
+
+		let horizon = mixer.position() + LOOKAHEAD_FRAMES;
+		let start = in_time_with.map_or(horizon, |frame| {
+			frame + horizon.saturating_sub(frame).div_ceil(FRAMES_PER_TICK) * FRAMES_PER_TICK
+		});
+		let origin = *self.origin.get_or_insert((project.tick(), start));
+		for _ in 0..MAX_TICKS_PER_RUN {
+			if Self::frame_of(origin, project.tick() + 1) > horizon {
+				break;
+			}
+			for note in project.step_pellets() {
+				if let Some(sample) = project.sample(note.sound) {
+					mixer.play_at(note, sample, Self::frame_of(origin, note.tick));
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::sound::mixer::Output;
+
+	fn playing_project() -> Project {
+		serde_json::from_value(serde_json::json!({
+			"version": 1,
+			"components": [],
+			"tempo": 120,
+			"pellets": [],
+		}))
+		.unwrap()
+	}
+
+	#[test]
+	fn linked_playback_starts_on_the_same_ticks() {
+		let (mut output, mut source) = Output::new();
+		let (first_mixer, second_mixer) = (output.mixer(), output.mixer());
+		let (mut first, mut second) = (Schedule::default(), Schedule::default());
+
+		first.run(&mut playing_project(), &first_mixer, None);
+		// partway through a tick, in stereo
+		source.by_ref().take(2 * 1000).for_each(drop);
+		second.run(&mut playing_project(), &second_mixer, first.origin_frame());
+
+		let (first, second) = (
+			first.origin_frame().unwrap(),
+			second.origin_frame().unwrap(),
+		);
+		assert!(second >= 1000 + LOOKAHEAD_FRAMES);
+		assert!(second - first < 1000 + LOOKAHEAD_FRAMES + FRAMES_PER_TICK);
+		assert_eq!((second - first) % FRAMES_PER_TICK, 0);
+	}
+}
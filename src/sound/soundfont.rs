@@ -0,0 +1,551 @@
+//! Presets read from SF2 files, so that whole General MIDI banks can be played without shipping samples.
+//!
+//! Only the first zone that plays a key is used, so stereo samples play only one of their channels, as mono.
+
+use std::ops::RangeInclusive;
+
+use super::bank::Sample;
+use super::instrument::Envelope;
+
+/// Generator operators, from the SF2 2.04 specification.
+mod generator {
+	pub const COARSE_TUNE: u16 = 51;
+	pub const FINE_TUNE: u16 = 52;
+	pub const INSTRUMENT: u16 = 41;
+	pub const KEY_RANGE: u16 = 43;
+	pub const OVERRIDING_ROOT_KEY: u16 = 58;
+	pub const SAMPLE_ID: u16 = 53;
+	pub const SAMPLE_MODES: u16 = 54;
+}
+
+/// How long a looping sample is held for, since notes have no length of their own.
+const HELD_SECONDS: f32 = 1.0;
+/// How long a held note takes to fade out at the end, so that it doesn't click.
+const HELD_RELEASE: f32 = 0.05;
+
+#[derive(Debug, Default)]
+struct Zone {
+	generators: Vec<(u16, [u8; 2])>,
+}
+
+impl Zone {
+	fn get(&self, operator: u16) -> Option<[u8; 2]> {
+		self
+			.generators
+			.iter()
+			.find(|&&(other, _)| other == operator)
+			.map(|&(_, amount)| amount)
+	}
+
+	fn signed(&self, operator: u16) -> Option<i16> {
+		self.get(operator).map(i16::from_le_bytes)
+	}
+
+	fn unsigned(&self, operator: u16) -> Option<u16> {
+		self.get(operator).map(u16::from_le_bytes)
+	}
+
+	fn key_range(&self) -> Option<RangeInclusive<u8>> {
+		self.get(generator::KEY_RANGE).map(|[low, high]| low..=high)
+	}
+}
+
+/// The zones of a preset or instrument. The first applies to all the others if it's global.
+#[derive(Debug, Default)]
+struct Zones {
+	global: Zone,
+	local: Vec<Zone>,
+}
+
+impl Zones {
+	/// The global zone is the first one, if it doesn't end with the generator that local zones end with.
+	fn new(mut zones: Vec<Zone>, terminal: u16) -> Self {
+		let is_global = zones.first().is_some_and(
+			|zone| !matches!(zone.generators.last(), Some(&(operator, _)) if operator == terminal),
+		);
+		let global = if is_global {
+			zones.remove(0)
+		} else {
+			Zone::default()
+		};
+		Self {
+			global,
+			local: zones,
+		}
+	}
+
+	/// The local zones that play `key`. A zone without a key range uses the global zone's, or plays every key if that has none either.
+	fn for_key(&self, key: u8) -> impl Iterator<Item = &Zone> {
+		self.local.iter().filter(move |zone| {
+			zone
+				.key_range()
+				.or_else(|| self.global.key_range())
+				.unwrap_or(0..=u8::MAX)
+				.contains(&key)
+		})
+	}
+
+	fn signed(&self, zone: &Zone, operator: u16) -> i16 {
+		zone
+			.signed(operator)
+			.or_else(|| self.global.signed(operator))
+			.unwrap_or(0)
+	}
+}
+
+#[derive(Debug)]
+struct Preset {
+	bank: u16,
+	number: u16,
+	zones: Zones,
+}
+
+#[derive(Debug)]
+struct SampleHeader {
+	start: usize,
+	end: usize,
+	loop_start: usize,
+	loop_end: usize,
+	rate: u32,
+	original_key: u8,
+	/// In cents.
+	correction: i8,
+}
+
+#[derive(Debug)]
+pub struct SoundFont {
+	/// Every sample's data, one after the other.
+	data: Vec<i16>,
+	presets: Vec<Preset>,
+	instruments: Vec<Zones>,
+	headers: Vec<SampleHeader>,
+}
+
+impl SoundFont {
+	pub fn parse(file: &[u8]) -> Result<Self, String> {
+		if file.get(..4) != Some(b"RIFF") || file.get(8..12) != Some(b"sfbk") {
+			return Err("not a SoundFont file".to_owned());
+		}
+		let lists = chunks(&file[12..])?;
+		let list = |kind: &[u8; 4]| {
+			lists
+				.iter()
+				.find(|(id, body)| id == b"LIST" && body.get(..4) == Some(kind))
+				.map(|(_, body)| chunks(&body[4..]))
+				.ok_or_else(|| format!("missing {} list", String::from_utf8_lossy(kind)))?
+		};
+		let sample_data = list(b"sdta")?;
+		let preset_data = list(b"pdta")?;
+
+		let data = chunk(&sample_data, *b"smpl")?
+			.chunks_exact(2)
+			.map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+			.collect();
+		let preset_headers = records(chunk(&preset_data, *b"phdr")?, 38);
+		// the bag index comes after a preset's name, number and bank
+		let presets = read_zones(
+			&preset_headers,
+			24,
+			&records(chunk(&preset_data, *b"pbag")?, 4),
+			&records(chunk(&preset_data, *b"pgen")?, 4),
+			generator::INSTRUMENT,
+		)?
+		.into_iter()
+		.zip(preset_headers)
+		.map(|(zones, header)| Preset {
+			bank: u16_at(header, 22),
+			number: u16_at(header, 20),
+			zones,
+		})
+		.collect();
+		// and after an instrument's name
+		let instruments = read_zones(
+			&records(chunk(&preset_data, *b"inst")?, 22),
+			20,
+			&records(chunk(&preset_data, *b"ibag")?, 4),
+			&records(chunk(&preset_data, *b"igen")?, 4),
+			generator::SAMPLE_ID,
+		)?;
+		let sample_headers = records(chunk(&preset_data, *b"shdr")?, 46);
+		// leaving out the terminal header, which has no sample
+		let headers = sample_headers[..sample_headers.len().saturating_sub(1)]
+			.iter()
+			.enumerate()
+			.map(|(index, header)| {
+				let rate = u32::from_le_bytes(header[36..40].try_into().unwrap());
+				if rate == 0 {
+					return Err(format!("sample {index} has a sample rate of 0"));
+				}
+				Ok(SampleHeader {
+					start: u32_at(header, 20),
+					end: u32_at(header, 24),
+					loop_start: u32_at(header, 28),
+					loop_end: u32_at(header, 32),
+					rate,
+					original_key: header[40],
+					correction: i8::from_le_bytes([header[41]]),
+				})
+			})
+			.collect::<Result<_, _>>()?;
+
+		Ok(Self {
+			data,
+			presets,
+			instruments,
+			headers,
+		})
+	}
+
+	fn preset(&self, bank: u16, number: u16) -> Option<&Preset> {
+		self
+			.presets
+			.iter()
+			.find(|preset| preset.bank == bank && preset.number == number)
+	}
+
+	pub fn has_preset(&self, bank: u16, number: u16) -> bool {
+		self.preset(bank, number).is_some()
+	}
+
+	/// The sound of preset `number` in `bank` playing MIDI key `key`, if it has one.
+	pub fn note(&self, bank: u16, number: u16, key: u8) -> Option<Sample> {
+		let preset = self.preset(bank, number)?;
+		for preset_zone in preset.zones.for_key(key) {
+			let Some(instrument) = preset_zone
+				.unsigned(generator::INSTRUMENT)
+				.and_then(|index| self.instruments.get(usize::from(index)))
+			else {
+				continue;
+			};
+			for zone in instrument.for_key(key) {
+				let Some(header) = zone
+					.unsigned(generator::SAMPLE_ID)
+					.and_then(|index| self.headers.get(usize::from(index)))
+				else {
+					continue;
+				};
+
+				let root = zone
+					.signed(generator::OVERRIDING_ROOT_KEY)
+					.or_else(|| instrument.global.signed(generator::OVERRIDING_ROOT_KEY))
+					.and_then(|key| u8::try_from(key).ok())
+					.unwrap_or(header.original_key);
+				// tuning in presets is added to the instrument's
+				let tuning = |operator| {
+					i32::from(instrument.signed(zone, operator))
+						+ i32::from(preset.zones.signed(preset_zone, operator))
+				};
+				let cents = (i32::from(key) - i32::from(root) + tuning(generator::COARSE_TUNE)) * 100
+					+ tuning(generator::FINE_TUNE)
+					+ i32::from(header.correction);
+				let speed = 2.0_f32.powf(az::cast::<_, f32>(cents) / 1200.0);
+
+				// modes 1 and 3 loop, 3 only until the note is released
+				let looping = instrument.signed(zone, generator::SAMPLE_MODES) & 1 == 1;
+				return Some(self.sample(header, looping, speed));
+			}
+		}
+		None
+	}
+
+	fn sample(&self, header: &SampleHeader, looping: bool, speed: f32) -> Sample {
+		let data = |range: std::ops::Range<usize>| {
+			self
+				.data
+				.get(range)
+				.unwrap_or_default()
+				.iter()
+				.map(|&sample| f32::from(sample) / 32768.0)
+		};
+		let can_loop = header.start <= header.loop_start
+			&& header.loop_start < header.loop_end
+			&& header.loop_end <= header.end;
+		if !(looping && can_loop) {
+			return Sample::new(1, header.rate, data(header.start..header.end).collect())
+				.resampled(speed);
+		}
+
+		// enough to last `HELD_SECONDS` once it's been sped up
+		let held =
+			az::saturating_cast::<_, usize>(HELD_SECONDS * az::cast::<_, f32>(header.rate) * speed);
+		let mut held_data: Vec<f32> = data(header.start..header.loop_end).collect();
+		let loop_data: Vec<f32> = data(header.loop_start..header.loop_end).collect();
+		while held_data.len() < held && !loop_data.is_empty() {
+			held_data.extend(&loop_data);
+		}
+		held_data.truncate(held.max(header.loop_end - header.start));
+		Sample::new(1, header.rate, held_data)
+			.resampled(speed)
+			.shaped(
+				1.0,
+				Envelope {
+					attack: 0.0,
+					release: HELD_RELEASE,
+				},
+			)
+	}
+}
+
+/// A RIFF chunk's ID and body.
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// The subchunks of a RIFF chunk's body.
+fn chunks(mut body: &[u8]) -> Result<Vec<Chunk<'_>>, String> {
+	let mut chunks = Vec::new();
+	while body.len() >= 8 {
+		let id: [u8; 4] = body[..4].try_into().unwrap();
+		let size = u32_at(body, 4);
+		let data = body
+			.get(8..8 + size)
+			.ok_or_else(|| format!("{} chunk is cut off", String::from_utf8_lossy(&id)))?;
+		chunks.push((id, data));
+		// chunks are padded to an even length
+		body = body.get(8 + size + size % 2..).unwrap_or_default();
+	}
+	Ok(chunks)
+}
+
+fn chunk<'a>(chunks: &[Chunk<'a>], id: [u8; 4]) -> Result<&'a [u8], String> {
+	chunks
+		.iter()
+		.find(|&&(other, _)| other == id)
+		.map(|&(_, body)| body)
+		.ok_or_else(|| format!("missing {} chunk", String::from_utf8_lossy(&id)))
+}
+
+fn records(chunk: &[u8], size: usize) -> Vec<&[u8]> {
+	chunk.chunks_exact(size).collect()
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+	u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> usize {
+	usize::try_from(u32::from_le_bytes(
+		bytes[offset..offset + 4].try_into().unwrap(),
+	))
+	.unwrap()
+}
+
+/// The zones of every preset or instrument header, leaving out the terminal header that ends the list.
+/// Each header's zones run up to the next header's, and each zone's generators up to the next zone's.
+fn read_zones(
+	headers: &[&[u8]],
+	bag_offset: usize,
+	bags: &[&[u8]],
+	generators: &[&[u8]],
+	terminal: u16,
+) -> Result<Vec<Zones>, String> {
+	let bag_index = |header: &[u8]| usize::from(u16_at(header, bag_offset));
+	let generator_index = |bag: &[u8]| usize::from(u16_at(bag, 0));
+	headers
+		.windows(2)
+		.map(|pair| {
+			let zones = bags
+				.get(bag_index(pair[0])..=bag_index(pair[1]))
+				.ok_or("zone out of range")?
+				.windows(2)
+				.map(|bags| {
+					let generators = generators
+						.get(generator_index(bags[0])..generator_index(bags[1]))
+						.ok_or("generator out of range")?;
+					Ok(Zone {
+						generators: generators
+							.iter()
+							.map(|generator| (u16_at(generator, 0), [generator[2], generator[3]]))
+							.collect(),
+					})
+				})
+				.collect::<Result<_, String>>()?;
+			Ok(Zones::new(zones, terminal))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RATE: u32 = 22050;
+
+	fn riff_chunk(id: [u8; 4], body: &[u8]) -> Vec<u8> {
+		let mut chunk = id.to_vec();
+		chunk.extend(u32::try_from(body.len()).unwrap().to_le_bytes());
+		chunk.extend(body);
+		if body.len() % 2 == 1 {
+			chunk.push(0);
+		}
+		chunk
+	}
+
+	fn list(kind: [u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+		riff_chunk(*b"LIST", &[kind.to_vec(), chunks.concat()].concat())
+	}
+
+	fn name() -> Vec<u8> {
+		vec![b'x'; 20]
+	}
+
+	fn preset_header(number: u16, bag: u16) -> Vec<u8> {
+		// bank 0, then the library, genre and morphology, which are unused
+		[
+			name(),
+			number.to_le_bytes().to_vec(),
+			vec![0; 2],
+			bag.to_le_bytes().to_vec(),
+			vec![0; 12],
+		]
+		.concat()
+	}
+
+	fn instrument_header(bag: u16) -> Vec<u8> {
+		[name(), bag.to_le_bytes().to_vec()].concat()
+	}
+
+	fn bag(generator: u16) -> Vec<u8> {
+		[generator.to_le_bytes(), [0; 2]].concat()
+	}
+
+	fn generator(operator: u16, amount: [u8; 2]) -> Vec<u8> {
+		[operator.to_le_bytes(), amount].concat()
+	}
+
+	fn sample_header(range: [u32; 4], rate: u32, original_key: u8) -> Vec<u8> {
+		let mut header = name();
+		for value in range.into_iter().chain([rate]) {
+			header.extend(value.to_le_bytes());
+		}
+		// no pitch correction, no linked sample, and a mono sample
+		header.extend([original_key, 0, 0, 0, 1, 0]);
+		header
+	}
+
+	/// Preset 5 in bank 0 plays an instrument with a global zone tuning it up an octave,
+	/// a zone for keys 0 to 63 playing sample 0 with its root key overridden to 48,
+	/// and a zone for keys 64 to 100 looping sample 1. The preset zone tunes it by `preset_tune` semitones.
+	fn soundfont(rate: u32, preset_tune: i16) -> Vec<u8> {
+		let samples: Vec<u8> = (0..1000 + 46 + 200 + 46)
+			.flat_map(|index: i16| (index % 100).to_le_bytes())
+			.collect();
+		let preset_data = [
+			riff_chunk(
+				*b"phdr",
+				&[preset_header(5, 0), preset_header(0, 1)].concat(),
+			),
+			riff_chunk(*b"pbag", &[bag(0), bag(3)].concat()),
+			riff_chunk(
+				*b"pgen",
+				&[
+					generator(generator::KEY_RANGE, [0, 127]),
+					generator(generator::COARSE_TUNE, preset_tune.to_le_bytes()),
+					generator(generator::INSTRUMENT, 0_u16.to_le_bytes()),
+					generator(0, [0; 2]),
+				]
+				.concat(),
+			),
+			riff_chunk(
+				*b"inst",
+				&[instrument_header(0), instrument_header(3)].concat(),
+			),
+			riff_chunk(*b"ibag", &[bag(0), bag(1), bag(4), bag(7)].concat()),
+			riff_chunk(
+				*b"igen",
+				&[
+					generator(generator::COARSE_TUNE, 12_i16.to_le_bytes()),
+					generator(generator::KEY_RANGE, [0, 63]),
+					generator(generator::OVERRIDING_ROOT_KEY, 48_i16.to_le_bytes()),
+					generator(generator::SAMPLE_ID, 0_u16.to_le_bytes()),
+					generator(generator::KEY_RANGE, [64, 100]),
+					generator(generator::SAMPLE_MODES, 1_i16.to_le_bytes()),
+					generator(generator::SAMPLE_ID, 1_u16.to_le_bytes()),
+					generator(0, [0; 2]),
+				]
+				.concat(),
+			),
+			riff_chunk(
+				*b"shdr",
+				&[
+					sample_header([0, 1000, 0, 0], rate, 60),
+					sample_header([1046, 1246, 1100, 1200], rate, 64),
+					sample_header([0; 4], 0, 0),
+				]
+				.concat(),
+			),
+		];
+		riff_chunk(
+			*b"RIFF",
+			&[
+				b"sfbk".to_vec(),
+				list(*b"INFO", &[riff_chunk(*b"ifil", &[2, 0, 1, 0])]),
+				list(*b"sdta", &[riff_chunk(*b"smpl", &samples)]),
+				list(*b"pdta", &preset_data),
+			]
+			.concat(),
+		)
+	}
+
+	fn frames(sample: &Sample) -> usize {
+		sample.source().count()
+	}
+
+	#[test]
+	fn finds_presets_by_bank_and_number() {
+		let soundfont = SoundFont::parse(&soundfont(RATE, 0)).unwrap();
+		assert!(soundfont.has_preset(0, 5));
+		assert!(!soundfont.has_preset(0, 6));
+		assert!(!soundfont.has_preset(1, 5));
+		assert!(soundfont.note(1, 5, 48).is_none());
+	}
+
+	#[test]
+	fn tunes_by_root_key_and_global_zone() {
+		let soundfont = SoundFont::parse(&soundfont(RATE, 0)).unwrap();
+		// the root key, an octave up, so twice as fast
+		assert_eq!(frames(&soundfont.note(0, 5, 48).unwrap()), 500);
+		assert_eq!(frames(&soundfont.note(0, 5, 36).unwrap()), 1000);
+		assert_eq!(frames(&soundfont.note(0, 5, 24).unwrap()), 2000);
+	}
+
+	#[test]
+	fn picks_zones_by_key_range() {
+		let soundfont = SoundFont::parse(&soundfont(RATE, 0)).unwrap();
+		let held = az::cast::<_, usize>(HELD_SECONDS * az::cast::<_, f32>(RATE));
+		assert!(frames(&soundfont.note(0, 5, 63).unwrap()) < 1000);
+		assert_eq!(frames(&soundfont.note(0, 5, 64).unwrap()), held);
+		assert_eq!(frames(&soundfont.note(0, 5, 100).unwrap()), held);
+		assert!(soundfont.note(0, 5, 101).is_none());
+	}
+
+	#[test]
+	fn loops_samples_until_held_long_enough() {
+		let soundfont = SoundFont::parse(&soundfont(RATE, 0)).unwrap();
+		let looped: Vec<f32> = soundfont.note(0, 5, 64).unwrap().source().collect();
+		// tuned up an octave by the global zone, so the 100 frame loop repeats every 50 frames
+		assert_eq!(looped[100..150], looped[150..200]);
+		assert_eq!(looped[100..150], looped[1000..1050]);
+	}
+
+	#[test]
+	fn adds_out_of_range_tuning_without_overflowing() {
+		let soundfont = SoundFont::parse(&soundfont(RATE, i16::MAX)).unwrap();
+		// so far up that nothing is left of the sample
+		assert_eq!(frames(&soundfont.note(0, 5, 48).unwrap()), 0);
+	}
+
+	#[test]
+	fn rejects_cut_off_files() {
+		let file = soundfont(RATE, 0);
+		for len in [file.len() - 10, file.len() / 2, 40, 8] {
+			assert!(SoundFont::parse(&file[..len]).is_err(), "cut off at {len}");
+		}
+	}
+
+	#[test]
+	fn rejects_sample_rate_of_zero() {
+		assert_eq!(
+			SoundFont::parse(&soundfont(0, 0)).unwrap_err(),
+			"sample 0 has a sample rate of 0"
+		);
+	}
+}